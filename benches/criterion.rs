@@ -8,6 +8,12 @@ fn bench_nows(c: &mut Criterion) {
     group.bench_function("minstant", |b| {
         b.iter(minstant::Instant::now);
     });
+    group.bench_function("minstant (fenced)", |b| {
+        b.iter(minstant::Instant::now_fenced);
+    });
+    group.bench_function("minstant (rdtscp)", |b| {
+        b.iter(minstant::Instant::now_rdtscp);
+    });
     group.bench_function("quanta", |b| {
         b.iter(quanta::Instant::now);
     });
@@ -0,0 +1,228 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! This module will be compiled when it's either linux_x86 or linux_x86_64.
+//!
+//! A calibration cache lets short-lived processes skip the measurement of the TSC
+//! frequency. An entry is only valid on the machine, boot and kernel it was measured
+//! on, against the same reference clock.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, read_to_string};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::config;
+use crate::{Calibration, FrequencySource, ReferenceClock};
+
+/// What a cached calibration is valid for.
+#[derive(Debug, Clone, PartialEq)]
+struct Key {
+    boot_id: String,
+    cpu_model: String,
+    kernel_release: String,
+    reference_clock: String,
+}
+
+impl Key {
+    fn current(reference_clock: ReferenceClock) -> Option<Key> {
+        let cpu_model = read_to_string("/proc/cpuinfo")
+            .ok()?
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key.trim() == "model name").then(|| value.trim().to_owned())
+            })
+            .unwrap_or_default();
+        Some(Key {
+            boot_id: read_to_string("/proc/sys/kernel/random/boot_id")
+                .ok()?
+                .trim()
+                .to_owned(),
+            cpu_model,
+            kernel_release: read_to_string("/proc/sys/kernel/osrelease")
+                .ok()?
+                .trim()
+                .to_owned(),
+            reference_clock: format!("{reference_clock:?}"),
+        })
+    }
+}
+
+/// Loads the calibration from the configured cache file, if it was measured in the
+/// current boot of the same machine and kernel.
+pub(crate) fn load(reference_clock: ReferenceClock) -> Option<Calibration> {
+    let path = config().calibration_cache.as_deref()?;
+    let key = Key::current(reference_clock)?;
+    parse(&read_to_string(path).ok()?, &key)
+}
+
+/// Refreshes the configured cache file with a freshly measured calibration. Errors are
+/// ignored, since the cache is only an optimization.
+pub(crate) fn store(calibration: &Calibration, reference_clock: ReferenceClock) {
+    let cacheable = matches!(
+        calibration.frequency_source,
+        FrequencySource::Measured | FrequencySource::Cpuid
+    ) && calibration.converged;
+    if !cacheable {
+        return;
+    }
+    if let (Some(path), Some(key)) = (
+        config().calibration_cache.as_deref(),
+        Key::current(reference_clock),
+    ) {
+        let _ = write_atomically(path, &serialize(calibration, &key));
+    }
+}
+
+/// Writes a temporary file next to `path`, then renames it over `path`, so that readers
+/// never observe a partially written file.
+///
+/// The temporary file has a random name, so that concurrent writers, including processes with
+/// the same PID in other namespaces, do not collide.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{:016x}.tmp", hasher.finish()));
+    write_through(path, Path::new(&tmp), contents)
+}
+
+/// Writes `contents` to `tmp`, then renames it over `path`.
+///
+/// The temporary file is created exclusively, so that a file or symlink planted at its path is
+/// never written through. A stale one is removed, which does not follow a symlink, and the
+/// creation is retried once.
+fn write_through(path: &Path, tmp: &Path, contents: &str) -> io::Result<()> {
+    let create = || {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(tmp)
+    };
+    let mut file = match create() {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            fs::remove_file(tmp)?;
+            create()?
+        }
+        file => file?,
+    };
+    let result = file
+        .write_all(contents.as_bytes())
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(tmp);
+    }
+    result
+}
+
+fn serialize(calibration: &Calibration, key: &Key) -> String {
+    format!(
+        "boot_id={}\ncpu_model={}\nkernel_release={}\nreference_clock={}\n\
+         cycles_per_second={}\nrelative_error={}\nstandard_error={}\n",
+        key.boot_id,
+        key.cpu_model,
+        key.kernel_release,
+        key.reference_clock,
+        calibration.cycles_per_second,
+        calibration.relative_error,
+        calibration.standard_error,
+    )
+}
+
+fn parse(contents: &str, key: &Key) -> Option<Calibration> {
+    let value = |name: &str| {
+        contents.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key == name).then_some(value)
+        })
+    };
+
+    let cached_key = Key {
+        boot_id: value("boot_id")?.to_owned(),
+        cpu_model: value("cpu_model")?.to_owned(),
+        kernel_release: value("kernel_release")?.to_owned(),
+        reference_clock: value("reference_clock")?.to_owned(),
+    };
+    if cached_key != *key {
+        return None;
+    }
+
+    let cycles_per_second = value("cycles_per_second")?.parse().ok()?;
+    if cycles_per_second == 0 {
+        return None;
+    }
+    Some(Calibration {
+        cycles_per_second,
+        frequency_source: FrequencySource::Cached,
+        iterations: 0,
+        elapsed: Duration::ZERO,
+        relative_error: value("relative_error")?.parse().ok()?,
+        standard_error: value("standard_error")?.parse().ok()?,
+        converged: true,
+        kernel_clock: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = Key {
+            boot_id: "0d6a1c3e-5b7a-4f1e-9c2d-3e4f5a6b7c8d".to_owned(),
+            cpu_model: "Intel(R) Xeon(R) CPU E5-2630 v4 @ 2.20GHz".to_owned(),
+            kernel_release: "6.1.0".to_owned(),
+            reference_clock: "MonotonicRaw".to_owned(),
+        };
+        let calibration = Calibration {
+            cycles_per_second: 2_199_998_765,
+            frequency_source: FrequencySource::Measured,
+            iterations: 2,
+            elapsed: Duration::from_millis(20),
+            relative_error: 1.5e-7,
+            standard_error: 3e-9,
+            converged: true,
+            kernel_clock: None,
+        };
+
+        let cached = parse(&serialize(&calibration, &key), &key).unwrap();
+        assert_eq!(cached.cycles_per_second, calibration.cycles_per_second);
+        assert_eq!(cached.frequency_source, FrequencySource::Cached);
+        assert_eq!(cached.relative_error, calibration.relative_error);
+        assert_eq!(cached.standard_error, calibration.standard_error);
+
+        let other_boot = Key {
+            boot_id: "other".to_owned(),
+            ..key.clone()
+        };
+        assert!(parse(&serialize(&calibration, &other_boot), &key).is_none());
+        assert!(parse("cycles_per_second=1\n", &key).is_none());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("minstant-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calibration");
+        let victim = dir.join("victim");
+        fs::write(&victim, "untouched").unwrap();
+
+        // A symlink planted at the temporary path is removed rather than followed.
+        let tmp = dir.join("calibration.tmp");
+        std::os::unix::fs::symlink(&victim, &tmp).unwrap();
+        write_through(&path, &tmp, "cycles_per_second=1\n").unwrap();
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cycles_per_second=1\n");
+        assert!(!tmp.exists());
+
+        write_atomically(&path, "cycles_per_second=2\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cycles_per_second=2\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
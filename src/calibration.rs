@@ -0,0 +1,217 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+use crate::mult_shift::{MultShift, NANOS_PER_SEC};
+
+/// The outcome of a [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) calibration,
+/// including the factors used to convert TSC cycles into nanoseconds.
+///
+/// A `Calibration` is a snapshot: it is returned by [`recalibrate()`](crate::recalibrate) and
+/// [`clock_info()`](crate::clock_info), and does not change once obtained, even if the clock
+/// is recalibrated again later.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub(crate) cycles_per_second: u64,
+    pub(crate) frequency_source: FrequencySource,
+    pub(crate) iterations: u32,
+    pub(crate) elapsed: Duration,
+    pub(crate) relative_error: f64,
+    pub(crate) standard_error: f64,
+    pub(crate) converged: bool,
+    /// The exact conversion of the kernel, if the frequency was obtained from it.
+    pub(crate) kernel_clock: Option<KernelClock>,
+}
+
+/// Where the frequency of TSC was obtained from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FrequencySource {
+    /// The frequency was measured against the monotonic clock of the operating system.
+    Measured,
+    /// The nominal frequency reported by CPUID leaf 0x15 or 0x16, confirmed by a short
+    /// measurement within its standard error.
+    Cpuid,
+    /// A previous calibration adjusted by a [`DriftMonitor`](crate::DriftMonitor) to match the
+    /// rate of the monotonic clock of the operating system.
+    Corrected,
+    /// An estimate used while the calibration runs in the background under the
+    /// `background-calibration` feature: the nominal frequency reported by CPUID, or else a
+    /// single short measurement.
+    Provisional,
+    /// The conversion from TSC cycles into nanoseconds the kernel publishes to perf events,
+    /// which is used exactly. Read unless disabled, see
+    /// [`Config::perf_event()`](crate::Config::perf_event).
+    PerfEvent,
+    /// The frequency was set by the `MINSTANT_TSC_HZ` environment variable.
+    Configured,
+    /// The frequency was measured by a previous process, and loaded from the calibration
+    /// cache, see [`Config::calibration_cache()`](crate::Config::calibration_cache).
+    Cached,
+}
+
+impl Calibration {
+    /// The frequency of the TSC, in cycles per second.
+    #[inline]
+    pub fn cycles_per_second(&self) -> u64 {
+        self.cycles_per_second
+    }
+
+    /// The number of nanoseconds a single TSC cycle lasts.
+    ///
+    /// This is informative only: conversions use the fixed-point [`mult()`](Calibration::mult)
+    /// and [`shift()`](Calibration::shift) instead.
+    #[inline]
+    pub fn nanos_per_cycle(&self) -> f64 {
+        NANOS_PER_SEC as f64 / self.cycles_per_second as f64
+    }
+
+    /// The multiplier converting cycles into nanoseconds, such that
+    /// `nanos = (cycles * mult) >> shift`, computed with 128-bit intermediates.
+    #[inline]
+    pub fn mult(&self) -> u64 {
+        self.nanos_factor().mult
+    }
+
+    /// The shift converting cycles into nanoseconds.
+    ///
+    /// *[See also the `mult()`](Calibration::mult).*
+    #[inline]
+    pub fn shift(&self) -> u32 {
+        self.nanos_factor().shift
+    }
+
+    /// The factor converting cycles into nanoseconds.
+    #[inline]
+    pub(crate) fn nanos_factor(&self) -> MultShift {
+        match self.kernel_clock {
+            Some(kernel_clock) => {
+                MultShift::new(kernel_clock.factor.mult, 1 << kernel_clock.factor.shift)
+            }
+            None => MultShift::new(NANOS_PER_SEC, self.cycles_per_second),
+        }
+    }
+
+    /// The factor converting nanoseconds into cycles.
+    #[inline]
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    pub(crate) fn cycles_factor(&self) -> MultShift {
+        match self.kernel_clock {
+            Some(kernel_clock) => {
+                MultShift::new(1 << kernel_clock.factor.shift, kernel_clock.factor.mult)
+            }
+            None => MultShift::new(self.cycles_per_second, NANOS_PER_SEC),
+        }
+    }
+
+    /// Where the frequency was obtained from.
+    #[inline]
+    pub fn frequency_source(&self) -> FrequencySource {
+        self.frequency_source
+    }
+
+    /// The number of measurement windows the calibration took.
+    #[inline]
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// The wall time spent on the calibration.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The relative error of the final estimate, i.e. the relative difference between the
+    /// last two measurement windows, or between the CPUID-reported frequency and the
+    /// measurement confirming it. It is zero for a [`Provisional`](FrequencySource::Provisional)
+    /// frequency, whose error is unknown.
+    #[inline]
+    pub fn relative_error(&self) -> f64 {
+        self.relative_error
+    }
+
+    /// The relative standard error of the measured frequency.
+    ///
+    /// Each measurement window samples TSC and the reference clock many times, each sample
+    /// bracketed by two TSC reads, and fits the frequency to them by least squares. This is
+    /// the standard error of that fit, relative to the frequency, so that
+    /// `3.0 * standard_error()` bounds the relative error of the measurement with high
+    /// confidence. It is infinite for a [`Provisional`](FrequencySource::Provisional) frequency.
+    #[inline]
+    pub fn standard_error(&self) -> f64 {
+        self.standard_error
+    }
+
+    /// Whether the measurement converged before reaching its time or iteration limit.
+    ///
+    /// *[See also the `CalibrationTimeout`](crate::CalibrationTimeout).*
+    #[inline]
+    pub fn converged(&self) -> bool {
+        self.converged
+    }
+}
+
+/// The conversion of the kernel from TSC cycles into the nanoseconds of perf timestamps:
+/// `nanos = time_zero + (cycles * mult) >> shift`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KernelClock {
+    pub(crate) factor: MultShift,
+    /// `None` if the kernel does not publish it.
+    pub(crate) time_zero: Option<u64>,
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+impl KernelClock {
+    /// The frequency of TSC implied by the conversion, rounded to the nearest cycle.
+    pub(crate) fn cycles_per_second(&self) -> u64 {
+        let scaled = (NANOS_PER_SEC as u128) << self.factor.shift;
+        let mult = self.factor.mult as u128;
+        ((scaled + mult / 2) / mult) as u64
+    }
+
+    /// Converts raw TSC cycles into a perf timestamp, wrapping like the kernel does.
+    pub(crate) fn perf_nanos(&self, tsc: u64) -> Option<u64> {
+        Some(self.time_zero?.wrapping_add(self.factor.apply(tsc) as u64))
+    }
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_clock() {
+        // The conversion of a 2 GHz TSC, with a `time_zero` wrapping around.
+        let kernel_clock = KernelClock {
+            factor: MultShift {
+                mult: 1 << 30,
+                shift: 31,
+            },
+            time_zero: Some(u64::MAX - 10),
+        };
+        assert_eq!(kernel_clock.cycles_per_second(), 2_000_000_000);
+        assert_eq!(kernel_clock.perf_nanos(2_000_000_000), Some(999_999_989));
+
+        let calibration = Calibration {
+            cycles_per_second: kernel_clock.cycles_per_second(),
+            frequency_source: FrequencySource::PerfEvent,
+            iterations: 0,
+            elapsed: Duration::ZERO,
+            relative_error: 0.0,
+            standard_error: 0.0,
+            converged: true,
+            kernel_clock: Some(kernel_clock),
+        };
+        for cycles in [0, 1, 12_345_678_901, u64::MAX] {
+            assert_eq!(
+                calibration.nanos_factor().apply(cycles),
+                kernel_clock.factor.apply(cycles)
+            );
+        }
+    }
+}
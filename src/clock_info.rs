@@ -0,0 +1,128 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::{fmt, io};
+
+use crate::{Calibration, Config, ReferenceClock, TscPolicy};
+
+/// A report describing how the clock behind [`Instant`](crate::Instant) was set up.
+///
+/// *[See also the `clock_info()`](crate::clock_info).*
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ClockInfo {
+    /// The clock currently backing [`Instant::now()`](crate::Instant::now).
+    pub source: ClockSource,
+    /// The latest TSC calibration, or `None` if TSC has never been calibrated.
+    pub calibration: Option<Calibration>,
+    /// Why TSC is not used, or `None` if it is.
+    pub tsc_unavailable_reason: Option<TscUnavailableReason>,
+    /// The policy deciding whether TSC is stable enough to be used.
+    pub tsc_policy: TscPolicy,
+    /// The clock TSC is calibrated against.
+    pub reference_clock: ReferenceClock,
+    /// The effective configuration.
+    pub config: Config,
+}
+
+/// The clock backing [`Instant::now()`](crate::Instant::now).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClockSource {
+    /// [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) is used.
+    Tsc,
+    /// TSC is unavailable, and the given fallback clock is used.
+    Fallback(FallbackClock),
+}
+
+/// The clock used when TSC is unavailable.
+///
+/// The clock is read from the `MINSTANT_FALLBACK_CLOCK` environment variable (`monotonic`,
+/// `monotonic_raw`, `monotonic_coarse`, `boottime` or `coarse`) when the clock is set up. It
+/// defaults to [`Coarse`](FallbackClock::Coarse) with the `fallback-coarse` feature, and to
+/// [`Monotonic`](FallbackClock::Monotonic) otherwise, which is also used if the selected clock
+/// is not supported. Every fallback clock counts nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FallbackClock {
+    /// The monotonic clock of the platform: `CLOCK_MONOTONIC` on Linux, and
+    /// [`std::time::Instant`] elsewhere.
+    Monotonic,
+    /// `CLOCK_MONOTONIC_RAW`, which is not adjusted by NTP. Linux only.
+    MonotonicRaw,
+    /// `CLOCK_MONOTONIC_COARSE`, which is faster to read but only advances at every tick of
+    /// the kernel. Linux only.
+    MonotonicCoarse,
+    /// `CLOCK_BOOTTIME`, which unlike `CLOCK_MONOTONIC` keeps advancing while the system is
+    /// suspended. Linux only.
+    Boottime,
+    /// [`coarsetime`](https://docs.rs/coarsetime), enabled by the `fallback-coarse` feature.
+    Coarse,
+}
+
+/// The reason why [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) is unavailable.
+///
+/// *[See also the `tsc_unavailable_reason()`](crate::tsc_unavailable_reason).*
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TscUnavailableReason {
+    /// The list of available clock sources could not be read from sysfs, which typically
+    /// happens in containers without `/sys`.
+    SysfsUnreadable(io::ErrorKind),
+    /// The kernel does not list `tsc` as an available clock source.
+    NotListedByKernel,
+    /// The kernel is not currently using `tsc` as its clock source, e.g. because its
+    /// watchdog marked TSC as unstable. Checked at startup under [`TscPolicy::Strict`], and
+    /// by [`check_clocksource()`](crate::check_clocksource) under any policy if the kernel
+    /// was using `tsc` at startup.
+    NotCurrentClocksource,
+    /// The CPU does not advertise an invariant TSC. Only checked under [`TscPolicy::Strict`].
+    NotInvariant,
+    /// The kernel command line marks TSC as unstable or selects another clock source.
+    /// Only checked under [`TscPolicy::Strict`].
+    DisabledByKernelCmdline,
+    /// The platform is not Linux on `x86` or `x86_64`.
+    UnsupportedArch,
+    /// The calibration did not produce a plausible frequency, e.g. TSC went backwards.
+    CalibrationDiverged,
+    /// The calibration did not converge within its time or iteration limit, and
+    /// [`CalibrationTimeout::Fallback`](crate::CalibrationTimeout::Fallback) is configured.
+    CalibrationTimedOut,
+    /// TSC was disabled by configuration, i.e. `MINSTANT_CLOCK=fallback`.
+    DisabledByConfig,
+    /// The skew of TSC across CPUs exceeds
+    /// [`Config::max_cross_core_skew`](crate::Config::max_cross_core_skew).
+    CrossCoreSkew,
+}
+
+impl fmt::Display for TscUnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TscUnavailableReason::SysfsUnreadable(kind) => {
+                write!(
+                    f,
+                    "failed to read available clock sources from sysfs: {kind}"
+                )
+            }
+            TscUnavailableReason::NotListedByKernel => {
+                write!(f, "tsc is not an available clock source")
+            }
+            TscUnavailableReason::NotCurrentClocksource => {
+                write!(f, "tsc is not the current clock source")
+            }
+            TscUnavailableReason::NotInvariant => write!(f, "tsc is not invariant"),
+            TscUnavailableReason::DisabledByKernelCmdline => {
+                write!(f, "tsc is disabled by the kernel command line")
+            }
+            TscUnavailableReason::UnsupportedArch => {
+                write!(f, "tsc is only supported on linux x86 and x86_64")
+            }
+            TscUnavailableReason::CalibrationDiverged => write!(f, "tsc calibration diverged"),
+            TscUnavailableReason::CalibrationTimedOut => {
+                write!(f, "tsc calibration did not converge in time")
+            }
+            TscUnavailableReason::DisabledByConfig => write!(f, "tsc is disabled by config"),
+            TscUnavailableReason::CrossCoreSkew => {
+                write!(f, "tsc skew across cpus exceeds the threshold")
+            }
+        }
+    }
+}
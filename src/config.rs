@@ -0,0 +1,361 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::FallbackClock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The configuration the clock is set up with, read from environment variables when the clock
+/// is set up:
+///
+/// | Variable                          | Value                                   |
+/// |-----------------------------------|-----------------------------------------|
+/// | `MINSTANT_CLOCK`                  | `auto`, `tsc` or `fallback`             |
+/// | `MINSTANT_TSC_HZ`                 | the TSC frequency, skipping calibration |
+/// | `MINSTANT_TSC_POLICY`             | `permissive` or `strict`                |
+/// | `MINSTANT_PERF_EVENT`             | `0` not to read the conversion of perf  |
+/// | `MINSTANT_REFERENCE_CLOCK`        | `monotonic_raw` or `monotonic`          |
+/// | `MINSTANT_CALIBRATION_MS`         | the maximum calibration time            |
+/// | `MINSTANT_CALIBRATION_ITERATIONS` | the maximum number of windows           |
+/// | `MINSTANT_CALIBRATION_TIMEOUT`    | `accept` or `fallback`                  |
+/// | `MINSTANT_CALIBRATION_CACHE`      | the path of the calibration cache       |
+/// | `MINSTANT_MAX_CROSS_CORE_SKEW_NS` | the maximum TSC skew across CPUs        |
+/// | `MINSTANT_FALLBACK_CLOCK`         | the clock used when TSC is unavailable  |
+///
+/// Values that cannot be parsed are ignored.
+///
+/// *[See also the `clock_info()`](crate::clock_info).*
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub(crate) clock: ClockPreference,
+    pub(crate) tsc_hz: Option<u64>,
+    pub(crate) tsc_policy: TscPolicy,
+    pub(crate) perf_event: bool,
+    pub(crate) reference_clock: ReferenceClock,
+    pub(crate) calibration_timeout: Duration,
+    pub(crate) max_calibration_iterations: u32,
+    pub(crate) on_calibration_timeout: CalibrationTimeout,
+    pub(crate) calibration_cache: Option<PathBuf>,
+    pub(crate) max_cross_core_skew: Option<Duration>,
+    pub(crate) fallback_clock: FallbackClock,
+}
+
+impl Config {
+    /// Which clock to use.
+    #[inline]
+    pub fn clock(&self) -> ClockPreference {
+        self.clock
+    }
+
+    /// The frequency of TSC, in cycles per second, if it is configured rather than calibrated.
+    #[inline]
+    pub fn tsc_hz(&self) -> Option<u64> {
+        self.tsc_hz
+    }
+
+    /// How strictly TSC has to be proven stable before it is used.
+    #[inline]
+    pub fn tsc_policy(&self) -> TscPolicy {
+        self.tsc_policy
+    }
+
+    /// Whether the conversion from TSC cycles into nanoseconds is read from the kernel through a
+    /// perf event, see [`FrequencySource::PerfEvent`](crate::FrequencySource::PerfEvent).
+    ///
+    /// Enabled by default. Opening the event calls `perf_event_open(2)`, which the seccomp
+    /// profiles of containers answer with `EPERM`, in which case the frequency is measured
+    /// instead. Stricter sandboxes that kill the process need `MINSTANT_PERF_EVENT=0`.
+    #[inline]
+    pub fn perf_event(&self) -> bool {
+        self.perf_event
+    }
+
+    /// The configured reference clock. The clock actually used is reported by
+    /// [`ClockInfo::reference_clock`](crate::ClockInfo::reference_clock).
+    #[inline]
+    pub fn reference_clock(&self) -> ReferenceClock {
+        self.reference_clock
+    }
+
+    /// The maximum time spent measuring the TSC frequency.
+    #[inline]
+    pub fn calibration_timeout(&self) -> Duration {
+        self.calibration_timeout
+    }
+
+    /// The maximum number of measurement windows.
+    #[inline]
+    pub fn max_calibration_iterations(&self) -> u32 {
+        self.max_calibration_iterations
+    }
+
+    /// What to do when the calibration has not converged within its limits.
+    #[inline]
+    pub fn on_calibration_timeout(&self) -> CalibrationTimeout {
+        self.on_calibration_timeout
+    }
+
+    /// The file caching the calibration across processes, if any.
+    ///
+    /// A cached calibration is reused as long as it was measured during the current boot, on
+    /// the same CPU model and kernel release, and against the same reference clock. The file
+    /// is replaced atomically after every fresh calibration.
+    #[inline]
+    pub fn calibration_cache(&self) -> Option<&Path> {
+        self.calibration_cache.as_deref()
+    }
+
+    /// The maximum skew of TSC across CPUs, if it is checked.
+    ///
+    /// When it is set, the skew is measured by [`verify_cross_core()`](crate::verify_cross_core)
+    /// at setup, and TSC is refused if it exceeds the maximum. It is not checked by default,
+    /// since the measurement pins a thread to every CPU in turn. Unless the `no-ctor` feature
+    /// is enabled, these threads are spawned from the initializer, before `main` runs.
+    #[inline]
+    pub fn max_cross_core_skew(&self) -> Option<Duration> {
+        self.max_cross_core_skew
+    }
+
+    /// The configured fallback clock. The clock actually used is reported by
+    /// [`ClockInfo::source`](crate::ClockInfo::source) once TSC is unavailable.
+    #[inline]
+    pub fn fallback_clock(&self) -> FallbackClock {
+        self.fallback_clock
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            clock: ClockPreference::default(),
+            tsc_hz: None,
+            tsc_policy: TscPolicy::default(),
+            perf_event: true,
+            reference_clock: ReferenceClock::default(),
+            calibration_timeout: Duration::from_secs(1),
+            max_calibration_iterations: 100,
+            on_calibration_timeout: CalibrationTimeout::default(),
+            calibration_cache: None,
+            max_cross_core_skew: None,
+            fallback_clock: if cfg!(feature = "fallback-coarse") {
+                FallbackClock::Coarse
+            } else {
+                FallbackClock::Monotonic
+            },
+        }
+    }
+}
+
+/// Which clock to use, regardless of whether
+/// [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) is deemed stable.
+///
+/// The preference is read from the `MINSTANT_CLOCK` environment variable (`auto`, `tsc` or
+/// `fallback`) when the clock is set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ClockPreference {
+    /// Use TSC if it is deemed stable according to the [`TscPolicy`], and the fallback clock
+    /// otherwise.
+    #[default]
+    Auto,
+    /// Use TSC without checking whether it is stable, e.g. in containers where `/sys` is not
+    /// mounted. TSC is then never demoted at runtime either. This has no effect on platforms
+    /// without TSC support.
+    Tsc,
+    /// Always use the fallback clock.
+    Fallback,
+}
+
+/// How strictly [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) has to be proven
+/// stable before it is used.
+///
+/// The policy is read from the `MINSTANT_TSC_POLICY` environment variable (`strict` or
+/// `permissive`) when the clock is set up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TscPolicy {
+    /// TSC is used as long as the kernel lists it as an available clock source.
+    #[default]
+    Permissive,
+    /// In addition, TSC has to be the current clock source of the kernel, the CPU has to
+    /// advertise an invariant TSC, and the kernel command line must not disable TSC.
+    Strict,
+}
+
+/// The clock [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) is calibrated against.
+///
+/// The clock is read from the `MINSTANT_REFERENCE_CLOCK` environment variable (`monotonic_raw`
+/// or `monotonic`) when the clock is set up. It only matters on Linux, where TSC is used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ReferenceClock {
+    /// `CLOCK_MONOTONIC_RAW`, which is not adjusted by NTP. A calibration against it is not
+    /// skewed if NTP happens to slew the clock meanwhile.
+    #[default]
+    MonotonicRaw,
+    /// `CLOCK_MONOTONIC`, i.e. [`std::time::Instant`], which NTP adjusts to match the rate of
+    /// the wall clock.
+    Monotonic,
+}
+
+/// What to do when the calibration of [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter)
+/// has not converged within its time or iteration limit.
+///
+/// The limits are read from the `MINSTANT_CALIBRATION_MS` (1000 by default) and
+/// `MINSTANT_CALIBRATION_ITERATIONS` (100 by default) environment variables, and the outcome
+/// from `MINSTANT_CALIBRATION_TIMEOUT` (`accept` or `fallback`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CalibrationTimeout {
+    /// Use the estimate of the most stable measurement window. The calibration is then
+    /// reported as not [converged](crate::Calibration::converged), along with its error.
+    #[default]
+    AcceptBestEstimate,
+    /// Fall back from TSC, or keep the previous calibration when recalibrating.
+    Fallback,
+}
+
+impl Config {
+    fn from_env() -> Config {
+        let mut config = Config::default();
+        if let Some(clock) = env_var("MINSTANT_CLOCK").and_then(ClockPreference::from_name) {
+            config.clock = clock;
+        }
+        config.tsc_hz = env_var("MINSTANT_TSC_HZ").and_then(parse_hz);
+        if let Some(policy) = env_var("MINSTANT_TSC_POLICY").and_then(TscPolicy::from_name) {
+            config.tsc_policy = policy;
+        }
+        if let Some(perf_event) = env_var("MINSTANT_PERF_EVENT").and_then(parse_bool) {
+            config.perf_event = perf_event;
+        }
+        if let Some(clock) = env_var("MINSTANT_REFERENCE_CLOCK").and_then(ReferenceClock::from_name)
+        {
+            config.reference_clock = clock;
+        }
+        if let Some(millis) = env_var("MINSTANT_CALIBRATION_MS").and_then(|v| v.parse().ok()) {
+            config.calibration_timeout = Duration::from_millis(millis);
+        }
+        if let Some(iterations) =
+            env_var("MINSTANT_CALIBRATION_ITERATIONS").and_then(|v| v.parse().ok())
+        {
+            config.max_calibration_iterations = iterations;
+        }
+        if let Some(outcome) =
+            env_var("MINSTANT_CALIBRATION_TIMEOUT").and_then(CalibrationTimeout::from_name)
+        {
+            config.on_calibration_timeout = outcome;
+        }
+        config.calibration_cache = std::env::var_os("MINSTANT_CALIBRATION_CACHE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        config.max_cross_core_skew = env_var("MINSTANT_MAX_CROSS_CORE_SKEW_NS")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_nanos);
+        if let Some(clock) = env_var("MINSTANT_FALLBACK_CLOCK").and_then(fallback_clock_from_name) {
+            config.fallback_clock = clock;
+        }
+        config
+    }
+}
+
+impl ReferenceClock {
+    fn from_name(name: String) -> Option<ReferenceClock> {
+        match name.as_str() {
+            "monotonic_raw" => Some(ReferenceClock::MonotonicRaw),
+            "monotonic" => Some(ReferenceClock::Monotonic),
+            _ => None,
+        }
+    }
+}
+
+impl CalibrationTimeout {
+    fn from_name(name: String) -> Option<CalibrationTimeout> {
+        match name.as_str() {
+            "accept" => Some(CalibrationTimeout::AcceptBestEstimate),
+            "fallback" => Some(CalibrationTimeout::Fallback),
+            _ => None,
+        }
+    }
+}
+
+impl ClockPreference {
+    fn from_name(name: String) -> Option<ClockPreference> {
+        match name.as_str() {
+            "auto" => Some(ClockPreference::Auto),
+            "tsc" => Some(ClockPreference::Tsc),
+            "fallback" => Some(ClockPreference::Fallback),
+            _ => None,
+        }
+    }
+}
+
+impl TscPolicy {
+    fn from_name(name: String) -> Option<TscPolicy> {
+        match name.as_str() {
+            "permissive" => Some(TscPolicy::Permissive),
+            "strict" => Some(TscPolicy::Strict),
+            _ => None,
+        }
+    }
+}
+
+fn fallback_clock_from_name(name: String) -> Option<FallbackClock> {
+    match name.as_str() {
+        "monotonic" => Some(FallbackClock::Monotonic),
+        "monotonic_raw" => Some(FallbackClock::MonotonicRaw),
+        "monotonic_coarse" => Some(FallbackClock::MonotonicCoarse),
+        "boottime" => Some(FallbackClock::Boottime),
+        "coarse" => Some(FallbackClock::Coarse),
+        _ => None,
+    }
+}
+
+/// Parses a frequency either as an integer, or in scientific notation such as `2.1e9`.
+fn parse_hz(value: String) -> Option<u64> {
+    let hz = value
+        .parse::<u64>()
+        .ok()
+        .or_else(|| value.parse::<f64>().ok().map(|hz| hz.round() as u64))?;
+    (hz > 0).then_some(hz)
+}
+
+fn parse_bool(value: String) -> Option<bool> {
+    match value.as_str() {
+        "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the configuration the clock is set up with.
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|value| value.trim().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hz() {
+        assert_eq!(parse_hz("2100000000".to_owned()), Some(2_100_000_000));
+        assert_eq!(parse_hz("2.1e9".to_owned()), Some(2_100_000_000));
+        assert_eq!(parse_hz("0".to_owned()), None);
+        assert_eq!(parse_hz("-1".to_owned()), None);
+        assert_eq!(parse_hz("fast".to_owned()), None);
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        assert_eq!(parse_bool("1".to_owned()), Some(true));
+        assert_eq!(parse_bool("on".to_owned()), Some(true));
+        assert_eq!(parse_bool("0".to_owned()), Some(false));
+        assert_eq!(parse_bool("false".to_owned()), Some(false));
+        assert_eq!(parse_bool("maybe".to_owned()), None);
+    }
+}
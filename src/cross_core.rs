@@ -0,0 +1,208 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::time::Duration;
+
+/// The offsets between the TSCs of the CPUs the process may run on, measured by
+/// [`verify_cross_core()`](crate::verify_cross_core).
+///
+/// The offset of each CPU is measured against the first one with a ping-pong protocol: a thread
+/// pinned to the first CPU reads its TSC, then pings a thread pinned to the other CPU, which
+/// reads its own TSC and pongs back. The TSC of the other CPU was read between the two reads of
+/// the first one, which bounds the offset. The tightest bounds over many round trips are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CrossCoreSkew {
+    /// The offset of every CPU, starting with the reference CPU, whose offset is zero.
+    pub offsets: Vec<CpuOffset>,
+    /// The largest difference between the offsets of two CPUs, in cycles.
+    pub max_skew_cycles: u64,
+    /// The largest difference between the offsets of two CPUs.
+    pub max_skew: Duration,
+}
+
+/// The offset of the TSC of a CPU relative to the reference CPU.
+///
+/// *[See also the `CrossCoreSkew`](crate::CrossCoreSkew).*
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CpuOffset {
+    /// The index of the CPU.
+    pub cpu: usize,
+    /// The estimated offset, in cycles. Positive values mean that the TSC of this CPU is ahead.
+    pub offset_cycles: i64,
+    /// Half the width of the interval the offset is known to be in, in cycles.
+    pub uncertainty_cycles: u64,
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+pub(crate) use self::linux::measure;
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{CpuOffset, CrossCoreSkew};
+    use crate::mult_shift::MultShift;
+
+    /// The number of round trips per CPU.
+    const ROUND_TRIPS: u64 = 1000;
+
+    /// How long to wait for the other thread before giving up, e.g. because its CPU went
+    /// offline.
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Measures the offset of every CPU in the affinity mask of the calling thread against
+    /// the first one. The calling thread itself is not pinned.
+    pub(crate) fn measure(nanos_factor: MultShift) -> io::Result<CrossCoreSkew> {
+        let cpus = allowed_cpus()?;
+        let Some(&reference) = cpus.first() else {
+            return Err(io::Error::other("no cpu is allowed"));
+        };
+
+        let mut offsets = vec![CpuOffset {
+            cpu: reference,
+            offset_cycles: 0,
+            uncertainty_cycles: 0,
+        }];
+        for &cpu in &cpus[1..] {
+            offsets.push(measure_pair(reference, cpu)?);
+        }
+
+        let min = offsets.iter().map(|offset| offset.offset_cycles).min();
+        let max = offsets.iter().map(|offset| offset.offset_cycles).max();
+        let max_skew_cycles = max.unwrap_or(0).abs_diff(min.unwrap_or(0));
+        let nanos = nanos_factor.apply(max_skew_cycles);
+        Ok(CrossCoreSkew {
+            offsets,
+            max_skew_cycles,
+            max_skew: Duration::from_nanos(nanos.min(u64::MAX as u128) as u64),
+        })
+    }
+
+    fn allowed_cpus() -> io::Result<Vec<usize>> {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        let ret =
+            unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect())
+    }
+
+    fn pin_to(cpu: usize) -> io::Result<()> {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        let ret = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Measures the offset of `cpu` against `reference`.
+    ///
+    /// Round trip `k` goes through `turn = 2k + 1` (ping) and `turn = 2k + 2` (pong). The
+    /// TSC of `cpu` is read between two reads of the TSC of `reference`, so
+    /// `t1 <= t2 - offset <= t3`, i.e. `t2 - t3 <= offset <= t2 - t1`.
+    fn measure_pair(reference: usize, cpu: usize) -> io::Result<CpuOffset> {
+        let turn = AtomicU64::new(0);
+        let remote_tsc = AtomicU64::new(0);
+
+        let (lower, upper) = thread::scope(|scope| {
+            let remote = scope.spawn(|| -> io::Result<()> {
+                let result = (|| {
+                    pin_to(cpu)?;
+                    for round in 0..ROUND_TRIPS {
+                        wait_for(&turn, 2 * round + 1)?;
+                        remote_tsc.store(tsc_ordered(), Ordering::Relaxed);
+                        turn.store(2 * round + 2, Ordering::Release);
+                    }
+                    Ok(())
+                })();
+                if result.is_err() {
+                    // Releases the reference thread.
+                    turn.store(u64::MAX, Ordering::Release);
+                }
+                result
+            });
+
+            let local = scope.spawn(|| -> io::Result<(i64, i64)> {
+                let bounds = measure_bounds(reference, &turn, &remote_tsc);
+                if bounds.is_err() {
+                    // Releases the remote thread.
+                    turn.store(u64::MAX, Ordering::Release);
+                }
+                bounds
+            });
+
+            let local = local.join().expect("local thread panicked");
+            let remote = remote.join().expect("remote thread panicked");
+            remote.and(local)
+        })?;
+
+        Ok(CpuOffset {
+            cpu,
+            offset_cycles: lower / 2 + upper / 2,
+            uncertainty_cycles: upper.abs_diff(lower) / 2,
+        })
+    }
+
+    /// Runs the reference side of the round trips, and returns the bounds of the offset.
+    fn measure_bounds(
+        reference: usize,
+        turn: &AtomicU64,
+        remote_tsc: &AtomicU64,
+    ) -> io::Result<(i64, i64)> {
+        pin_to(reference)?;
+        let (mut lower, mut upper) = (i64::MIN, i64::MAX);
+        for round in 0..ROUND_TRIPS {
+            let t1 = tsc_ordered();
+            turn.store(2 * round + 1, Ordering::Release);
+            wait_for(turn, 2 * round + 2)?;
+            let t3 = tsc_ordered();
+            let t2 = remote_tsc.load(Ordering::Relaxed);
+            lower = lower.max(t2.wrapping_sub(t3) as i64);
+            upper = upper.min(t2.wrapping_sub(t1) as i64);
+        }
+        Ok((lower, upper))
+    }
+
+    fn wait_for(turn: &AtomicU64, expected: u64) -> io::Result<()> {
+        let start = Instant::now();
+        loop {
+            match turn.load(Ordering::Acquire) {
+                current if current == expected => return Ok(()),
+                u64::MAX => return Err(io::Error::other("peer failed")),
+                _ if start.elapsed() > TIMEOUT => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "cpu did not respond",
+                    ))
+                }
+                _ => std::hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Reads TSC after all earlier loads completed, and before any later instruction starts.
+    #[inline]
+    fn tsc_ordered() -> u64 {
+        #[cfg(target_arch = "x86")]
+        use core::arch::x86::{_mm_lfence, _rdtsc};
+        #[cfg(target_arch = "x86_64")]
+        use core::arch::x86_64::{_mm_lfence, _rdtsc};
+
+        unsafe {
+            _mm_lfence();
+            let tsc = _rdtsc();
+            _mm_lfence();
+            tsc
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::watcher::Worker;
+use crate::Instant;
+
+/// A monitor comparing the time measured by [`Instant`](crate::Instant) with the clock of the
+/// operating system that TSC is calibrated against, see
+/// [`Config::reference_clock`](crate::Config::reference_clock).
+///
+/// The TSC rate seen by a virtual machine may change after a live migration, which silently
+/// skews every duration measured afterwards. Each [`poll()`](DriftMonitor::poll) samples both
+/// clocks and estimates the skew accumulated since the monitor was created, or since the last
+/// correction. When a threshold is crossed, the monitor invokes the [`on_drift`](DriftMonitor::on_drift)
+/// callback, and corrects the TSC rate if [`correct_rate`](DriftMonitor::correct_rate) is enabled.
+///
+/// A rate correction only changes the factor used to convert cycles into nanoseconds, so
+/// [`Instant::now()`](crate::Instant::now) never jumps.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use minstant::DriftMonitor;
+///
+/// let mut monitor = DriftMonitor::new()
+///     .max_rate_error(0.0001)
+///     .on_drift(|drift| println!("clock drifted: {:?}", drift));
+///
+/// let drift = monitor.poll();
+/// println!("skew: {}ns", drift.skew_nanos);
+///
+/// // Or poll from a background thread.
+/// let handle = monitor.spawn(Duration::from_secs(60)).unwrap();
+/// drop(handle);
+/// ```
+pub struct DriftMonitor {
+    base: Instant,
+    base_reference: Duration,
+    max_skew: Option<Duration>,
+    max_rate_error: Option<f64>,
+    correct_rate: bool,
+    on_drift: Option<DriftCallback>,
+}
+
+type DriftCallback = Box<dyn FnMut(&Drift) + Send>;
+
+/// The drift estimated by a [`DriftMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct Drift {
+    /// The time elapsed on the reference clock since the monitor was created or last corrected
+    /// the rate.
+    pub elapsed: Duration,
+    /// The time measured by [`Instant`](crate::Instant) minus the time measured by the reference
+    /// clock, in nanoseconds. Positive values mean that `Instant` runs ahead.
+    pub skew_nanos: i64,
+    /// The skew relative to the elapsed time.
+    pub rate_error: f64,
+}
+
+impl DriftMonitor {
+    /// Creates a monitor, taking the current time of both clocks as the baseline.
+    ///
+    /// No threshold is set by default.
+    pub fn new() -> DriftMonitor {
+        DriftMonitor {
+            base: Instant::now(),
+            base_reference: crate::reference_now(),
+            max_skew: None,
+            max_rate_error: None,
+            correct_rate: false,
+            on_drift: None,
+        }
+    }
+
+    /// Sets the maximum accumulated skew before the drift is reported.
+    pub fn max_skew(mut self, max_skew: Duration) -> DriftMonitor {
+        self.max_skew = Some(max_skew);
+        self
+    }
+
+    /// Sets the maximum relative rate error before the drift is reported.
+    pub fn max_rate_error(mut self, max_rate_error: f64) -> DriftMonitor {
+        self.max_rate_error = Some(max_rate_error);
+        self
+    }
+
+    /// Sets a callback invoked whenever a threshold is crossed.
+    pub fn on_drift(mut self, on_drift: impl FnMut(&Drift) + Send + 'static) -> DriftMonitor {
+        self.on_drift = Some(Box::new(on_drift));
+        self
+    }
+
+    /// Whether to correct the TSC rate whenever a threshold is crossed. Disabled by default.
+    ///
+    /// A correction only compensates half of the measured rate error, and changes the rate by
+    /// at most 0.1%, so that a single noisy poll cannot skew the clock; a persistent error is
+    /// corrected over the following polls. After a correction, the baseline is reset to the
+    /// current time.
+    pub fn correct_rate(mut self, correct_rate: bool) -> DriftMonitor {
+        self.correct_rate = correct_rate;
+        self
+    }
+
+    /// Samples both clocks and returns the drift accumulated since the baseline.
+    pub fn poll(&mut self) -> Drift {
+        let now = Instant::now();
+        let now_reference = crate::reference_now();
+
+        let elapsed = now_reference.saturating_sub(self.base_reference);
+        let measured = now.duration_since(self.base);
+        let skew_nanos = (measured.as_nanos() as i128 - elapsed.as_nanos() as i128)
+            .clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        let rate_error = if elapsed.is_zero() {
+            0.0
+        } else {
+            skew_nanos as f64 / elapsed.as_nanos() as f64
+        };
+        let drift = Drift {
+            elapsed,
+            skew_nanos,
+            rate_error,
+        };
+
+        let exceeded = self
+            .max_skew
+            .is_some_and(|max_skew| skew_nanos.unsigned_abs() > max_skew.as_nanos() as u64)
+            || self
+                .max_rate_error
+                .is_some_and(|max_rate_error| rate_error.abs() > max_rate_error);
+        if exceeded {
+            if let Some(on_drift) = &mut self.on_drift {
+                on_drift(&drift);
+            }
+            if self.correct_rate {
+                crate::correct_rate(rate_error);
+                self.base = now;
+                self.base_reference = now_reference;
+            }
+        }
+
+        drift
+    }
+
+    /// Spawns a thread polling the monitor every `interval`. The thread stops when the returned
+    /// handle is dropped.
+    pub fn spawn(mut self, interval: Duration) -> io::Result<DriftMonitorHandle> {
+        let worker = Worker::spawn("minstant-drift", interval, move || {
+            self.poll();
+            true
+        })?;
+        Ok(DriftMonitorHandle { _worker: worker })
+    }
+}
+
+impl Default for DriftMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DriftMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DriftMonitor")
+            .field("base", &self.base)
+            .field("base_reference", &self.base_reference)
+            .field("max_skew", &self.max_skew)
+            .field("max_rate_error", &self.max_rate_error)
+            .field("correct_rate", &self.correct_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A handle to a [`DriftMonitor`] polled by a background thread.
+///
+/// *[See also the `DriftMonitor::spawn()`](crate::DriftMonitor::spawn).*
+#[derive(Debug)]
+pub struct DriftMonitorHandle {
+    _worker: Worker,
+}
@@ -0,0 +1,164 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! This module is compiled where instants are converted with fixed-point factors: on the hot
+//! path of TSC, or without `std`.
+
+use core::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
+
+use crate::mult_shift::MultShift;
+
+impl MultShift {
+    /// The ratio of an identity conversion, e.g. when cycles are nanoseconds.
+    pub(crate) const IDENTITY: MultShift = MultShift {
+        mult: 1 << 63,
+        shift: 63,
+    };
+
+    /// Returns `(value * mult) >> shift`, which cannot overflow.
+    #[inline]
+    pub(crate) fn apply(self, value: u64) -> u128 {
+        (value as u128 * self.mult as u128) >> self.shift
+    }
+
+    /// Returns `(value * mult) >> shift`, or `None` if the result does not fit in a `u64`.
+    pub(crate) fn checked_apply(self, value: u128) -> Option<u64> {
+        let (high, low) = ((value >> 64) as u64, value as u64);
+        let high_product = (high as u128).checked_mul(self.mult as u128)?;
+        let low_product = low as u128 * self.mult as u128;
+
+        // value * mult = high_product * 2^64 + low_product
+        let result = if self.shift >= 64 {
+            high_product.checked_add(low_product >> 64)? >> (self.shift - 64)
+        } else {
+            let high_shifted = high_product.checked_mul(1 << (64 - self.shift))?;
+            high_shifted.checked_add(low_product >> self.shift)?
+        };
+
+        u64::try_from(result).ok()
+    }
+}
+
+/// The factors converting between cycles and nanoseconds, guarded by a sequence
+/// lock: readers retry if new factors were published concurrently, so a `mult`
+/// is never paired with the `shift` of another calibration.
+pub(crate) struct AtomicFactors {
+    seq: AtomicUsize,
+    nanos: AtomicMultShift,
+    cycles: AtomicMultShift,
+}
+
+impl AtomicFactors {
+    pub(crate) const fn new() -> AtomicFactors {
+        AtomicFactors {
+            seq: AtomicUsize::new(0),
+            nanos: AtomicMultShift::new(MultShift::IDENTITY),
+            cycles: AtomicMultShift::new(MultShift::IDENTITY),
+        }
+    }
+
+    /// Returns the factors converting (1) cycles into nanoseconds and (2) nanoseconds
+    /// into cycles.
+    #[inline]
+    pub(crate) fn load(&self) -> (MultShift, MultShift) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let nanos = self.nanos.load();
+            let cycles = self.cycles.load();
+            fence(Ordering::Acquire);
+            if seq & 1 == 0 && self.seq.load(Ordering::Relaxed) == seq {
+                return (nanos, cycles);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Must be serialized with other stores.
+    pub(crate) fn store(&self, nanos: MultShift, cycles: MultShift) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.nanos.store(nanos);
+        self.cycles.store(cycles);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
+/// A [`MultShift`] stored in 32-bit atomics, which every target supports. Its fields
+/// are only consistent with each other under the sequence lock of [`AtomicFactors`].
+struct AtomicMultShift {
+    mult_high: AtomicU32,
+    mult_low: AtomicU32,
+    shift: AtomicU32,
+}
+
+impl AtomicMultShift {
+    const fn new(factor: MultShift) -> AtomicMultShift {
+        AtomicMultShift {
+            mult_high: AtomicU32::new((factor.mult >> 32) as u32),
+            mult_low: AtomicU32::new(factor.mult as u32),
+            shift: AtomicU32::new(factor.shift),
+        }
+    }
+
+    #[inline]
+    fn load(&self) -> MultShift {
+        let high = self.mult_high.load(Ordering::Relaxed) as u64;
+        let low = self.mult_low.load(Ordering::Relaxed) as u64;
+        MultShift {
+            mult: high << 32 | low,
+            shift: self.shift.load(Ordering::Relaxed),
+        }
+    }
+
+    fn store(&self, factor: MultShift) {
+        self.mult_high
+            .store((factor.mult >> 32) as u32, Ordering::Relaxed);
+        self.mult_low.store(factor.mult as u32, Ordering::Relaxed);
+        self.shift.store(factor.shift, Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::mult_shift::NANOS_PER_SEC;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_mult_shift_is_exact(
+            numerator in 1u64..=1 << 40,
+            denominator in 1u64..=1 << 40,
+            value: u64,
+        ) {
+            let exact = value as u128 * numerator as u128 / denominator as u128;
+            let ratio = MultShift::new(numerator, denominator);
+            let tolerance = 1 + (exact >> 62);
+            prop_assert!(ratio.mult >= 1 << 63);
+            prop_assert!(ratio.apply(value).abs_diff(exact) <= tolerance);
+            if let Some(result) = ratio.checked_apply(value as u128) {
+                prop_assert_eq!(result as u128, ratio.apply(value));
+            } else {
+                prop_assert!(ratio.apply(value) > u64::MAX as u128);
+            }
+        }
+
+        #[test]
+        fn test_checked_apply_large_values(
+            cycles_per_second in 1_000_000u64..100_000_000_000,
+            nanos: u128,
+        ) {
+            let ratio = MultShift::new(cycles_per_second, NANOS_PER_SEC);
+            let exact = nanos
+                .checked_mul(cycles_per_second as u128)
+                .map(|product| product / NANOS_PER_SEC as u128);
+            match ratio.checked_apply(nanos) {
+                Some(cycles) => {
+                    let exact = exact.unwrap();
+                    prop_assert!((cycles as u128).abs_diff(exact) <= 1 + (exact >> 62));
+                }
+                None => prop_assert!(exact.unwrap_or(u128::MAX) >= u64::MAX as u128),
+            }
+        }
+    }
+}
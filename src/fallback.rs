@@ -0,0 +1,135 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The clock used when TSC is unavailable. Every fallback clock counts nanoseconds, so that
+//! an instant taken from it is converted into time with a factor of one.
+
+use std::sync::OnceLock;
+
+use crate::config::config;
+use crate::FallbackClock;
+
+/// The fallback clock, resolved from the configuration when it is first read.
+static FALLBACK_CLOCK: OnceLock<FallbackClock> = OnceLock::new();
+
+/// Returns the fallback clock: the configured one, or [`FallbackClock::Monotonic`] if it is
+/// not supported on this platform or by this build.
+pub(crate) fn clock() -> FallbackClock {
+    *FALLBACK_CLOCK.get_or_init(|| {
+        let configured = config().fallback_clock;
+        if read(configured).is_some() {
+            configured
+        } else {
+            FallbackClock::Monotonic
+        }
+    })
+}
+
+/// Reads the fallback clock, in nanoseconds.
+#[inline]
+pub(crate) fn now() -> u64 {
+    read(clock()).unwrap_or(0)
+}
+
+/// Reads `clock`, or returns `None` if it is not supported.
+#[cfg(target_os = "linux")]
+fn read(clock: FallbackClock) -> Option<u64> {
+    let clockid = match clock {
+        FallbackClock::Monotonic => libc::CLOCK_MONOTONIC,
+        FallbackClock::MonotonicRaw => libc::CLOCK_MONOTONIC_RAW,
+        FallbackClock::MonotonicCoarse => libc::CLOCK_MONOTONIC_COARSE,
+        FallbackClock::Boottime => libc::CLOCK_BOOTTIME,
+        FallbackClock::Coarse => return read_coarse(),
+    };
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clockid, &mut ts) } != 0 {
+        return None;
+    }
+    Some(
+        (ts.tv_sec as u64)
+            .wrapping_mul(1_000_000_000)
+            .wrapping_add(ts.tv_nsec as u64),
+    )
+}
+
+/// Reads `clock`, or returns `None` if it is not supported. The monotonic clock of the
+/// platform is read as the nanoseconds elapsed since it was first read.
+#[cfg(not(target_os = "linux"))]
+fn read(clock: FallbackClock) -> Option<u64> {
+    static ORIGIN: OnceLock<web_time::Instant> = OnceLock::new();
+    match clock {
+        FallbackClock::Monotonic => Some(
+            ORIGIN
+                .get_or_init(web_time::Instant::now)
+                .elapsed()
+                .as_nanos() as u64,
+        ),
+        FallbackClock::Coarse => read_coarse(),
+        _ => None,
+    }
+}
+
+/// The resolution of the fallback clock, in nanoseconds: a reading may lag behind the time it
+/// is taken at by up to this much. `coarsetime` reads `CLOCK_MONOTONIC_COARSE` on Linux.
+#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+pub(crate) fn resolution() -> u64 {
+    let clockid = match clock() {
+        FallbackClock::Monotonic => libc::CLOCK_MONOTONIC,
+        FallbackClock::MonotonicRaw => libc::CLOCK_MONOTONIC_RAW,
+        FallbackClock::MonotonicCoarse | FallbackClock::Coarse => libc::CLOCK_MONOTONIC_COARSE,
+        FallbackClock::Boottime => libc::CLOCK_BOOTTIME,
+    };
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_getres(clockid, &mut ts) } != 0 {
+        return 0;
+    }
+    (ts.tv_sec as u64)
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(ts.tv_nsec as u64)
+}
+
+#[cfg(feature = "fallback-coarse")]
+fn read_coarse() -> Option<u64> {
+    let coarse = coarsetime::Instant::now_without_cache_update();
+    Some(coarsetime::Duration::from_ticks(coarse.as_ticks()).as_nanos())
+}
+
+#[cfg(not(feature = "fallback-coarse"))]
+fn read_coarse() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        assert!(read(FallbackClock::Monotonic).is_some());
+        assert_eq!(
+            read(FallbackClock::Coarse).is_some(),
+            cfg!(feature = "fallback-coarse")
+        );
+        #[cfg(target_os = "linux")]
+        for clock in [
+            FallbackClock::MonotonicRaw,
+            FallbackClock::MonotonicCoarse,
+            FallbackClock::Boottime,
+        ] {
+            let before = read(clock).unwrap();
+            assert!(read(clock).unwrap() >= before, "{:?}", clock);
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    fn test_resolution() {
+        let resolution = resolution();
+        assert!(resolution > 0 && resolution < 1_000_000_000);
+    }
+}
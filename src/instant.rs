@@ -1,10 +1,9 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::{
+use core::{
     ops::{Add, AddAssign, Sub, SubAssign},
     time::Duration,
 };
-use web_time::{SystemTime, UNIX_EPOCH};
 
 /// A measurement of a monotonically nondecreasing clock. Similar to
 /// [`std::time::Instant`](std::time::Instant) but is faster and more
@@ -20,6 +19,9 @@ impl Instant {
     #[inline]
     /// Returns an instant corresponding to "now".
     ///
+    /// Without the `std` feature, this reads TSC directly, and is only available on `x86` and
+    /// `x86_64`.
+    ///
     /// # Examples
     ///
     /// ```
@@ -27,10 +29,95 @@ impl Instant {
     ///
     /// let now = Instant::now();
     /// ```
+    #[cfg(any(feature = "std", target_arch = "x86", target_arch = "x86_64"))]
     pub fn now() -> Instant {
         Instant(crate::current_cycle())
     }
 
+    /// Returns an instant corresponding to "now", read only once all earlier instructions
+    /// have completed (`lfence; rdtsc`).
+    ///
+    /// [`Instant::now()`] may read TSC before the code preceding it has completed, which
+    /// matters when measuring regions of less than a few hundred nanoseconds. This falls back
+    /// to [`Instant::now()`] when TSC is unavailable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// let now = Instant::now_fenced();
+    /// ```
+    #[inline]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn now_fenced() -> Instant {
+        Instant(crate::current_cycle_ordered(crate::TscOrdering::Fenced))
+    }
+
+    /// Returns an instant corresponding to "now", read with `rdtscp`, which waits for all
+    /// earlier instructions to complete, but lets later ones start before.
+    ///
+    /// This uses `lfence; rdtsc` on CPUs without `rdtscp`, and falls back to
+    /// [`Instant::now()`] when TSC is unavailable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// let now = Instant::now_rdtscp();
+    /// ```
+    #[inline]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn now_rdtscp() -> Instant {
+        Instant(crate::current_cycle_ordered(crate::TscOrdering::Rdtscp))
+    }
+
+    /// Returns an instant marking the start of a measured region, following Intel's
+    /// guidance for benchmarking code: `cpuid` keeps earlier instructions from running into
+    /// the region, then `rdtsc` reads TSC.
+    ///
+    /// The region should end with [`Instant::bench_stop()`]. Both fall back to
+    /// [`Instant::now()`] when TSC is unavailable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// let start = Instant::bench_start();
+    /// // Code snippet to measure
+    /// let duration = (Instant::bench_stop() - start).to_duration();
+    /// ```
+    #[inline]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn bench_start() -> Instant {
+        Instant(crate::current_cycle_ordered(crate::TscOrdering::Start))
+    }
+
+    /// Returns an instant marking the end of a region started by [`Instant::bench_start()`]:
+    /// `rdtscp` reads TSC once the region has completed, then `cpuid` keeps later instructions
+    /// from running before the read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// let start = Instant::bench_start();
+    /// // Code snippet to measure
+    /// let duration = (Instant::bench_stop() - start).to_duration();
+    /// ```
+    #[inline]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn bench_stop() -> Instant {
+        Instant(crate::current_cycle_ordered(crate::TscOrdering::Stop))
+    }
+
     /// Returns the amount of time elapsed from another instant to this one,
     /// or zero duration if that instant is later than this one.
     ///
@@ -47,6 +134,8 @@ impl Instant {
     /// use std::thread::sleep;
     ///
     /// use minstant::Instant;
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
     ///
     /// let now = Instant::now();
     /// sleep(Duration::new(1, 0));
@@ -68,6 +157,8 @@ impl Instant {
     /// use std::thread::sleep;
     ///
     /// use minstant::Instant;
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
     ///
     /// let now = Instant::now();
     /// sleep(Duration::new(1, 0));
@@ -76,9 +167,7 @@ impl Instant {
     /// println!("{:?}", now.checked_duration_since(new_now)); // None
     /// ```
     pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
-        Some(Duration::from_nanos(
-            (self.0.checked_sub(earlier.0)? as f64 * crate::nanos_per_cycle()) as u64,
-        ))
+        duration_from_nanos(crate::cycles_to_nanos(self.0.checked_sub(earlier.0)?))
     }
 
     /// Returns the amount of time elapsed from another instant to this one,
@@ -91,6 +180,8 @@ impl Instant {
     /// use std::thread::sleep;
     ///
     /// use minstant::Instant;
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
     ///
     /// let now = Instant::now();
     /// sleep(Duration::new(1, 0));
@@ -102,6 +193,67 @@ impl Instant {
         self.checked_duration_since(earlier).unwrap_or_default()
     }
 
+    /// Returns the number of cycles elapsed from another instant to this one, or zero if that
+    /// instant is later than this one.
+    ///
+    /// Unlike [`duration_since()`](Instant::duration_since), this does not convert the cycles into
+    /// time, so spans can be accumulated and compared in cycles, and converted once with
+    /// [`CycleDuration::to_duration()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::{CycleDuration, Instant};
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
+    ///
+    /// let mut total = CycleDuration::ZERO;
+    /// for _ in 0..10 {
+    ///     let start = Instant::now();
+    ///     // Code snippet to measure
+    ///     total += Instant::now().cycles_since(start);
+    /// }
+    /// println!("{:?}", total.to_duration());
+    /// ```
+    #[inline]
+    pub fn cycles_since(&self, earlier: Instant) -> CycleDuration {
+        self.checked_cycles_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the number of cycles elapsed from another instant to this one, or `None` if that
+    /// instant is later than this one.
+    #[inline]
+    pub fn checked_cycles_since(&self, earlier: Instant) -> Option<CycleDuration> {
+        self.0.checked_sub(earlier.0).map(CycleDuration)
+    }
+
+    /// Returns the raw value of the counter behind this instant.
+    ///
+    /// *[See also the `Cycles`](crate::Cycles).*
+    #[inline]
+    pub const fn as_cycles(&self) -> Cycles {
+        self.0
+    }
+
+    /// Returns an instant from a raw value of the counter, as returned by
+    /// [`as_cycles()`](Instant::as_cycles).
+    ///
+    /// Without the `std` feature, this also lets applications read a counter themselves, e.g.
+    /// on platforms other than `x86` and `x86_64`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// let now = Instant::now();
+    /// assert_eq!(Instant::from_cycles(now.as_cycles()), now);
+    /// ```
+    #[inline]
+    pub const fn from_cycles(cycles: Cycles) -> Instant {
+        Instant(cycles)
+    }
+
     /// Returns the amount of time elapsed since this instant was created.
     ///
     /// # Panics
@@ -113,6 +265,7 @@ impl Instant {
     /// # Examples
     ///
     /// ```
+    /// # #[cfg(feature = "std")] {
     /// use std::time::Duration;
     /// use std::thread::sleep;
     ///
@@ -122,10 +275,12 @@ impl Instant {
     /// let three_secs = Duration::from_secs(3);
     /// sleep(three_secs);
     /// assert!(instant.elapsed() >= three_secs);
+    /// # }
     /// ```
     #[inline]
+    #[cfg(any(feature = "std", target_arch = "x86", target_arch = "x86_64"))]
     pub fn elapsed(&self) -> Duration {
-        Instant::now() - *self
+        Instant::now().duration_since(*self)
     }
 
     /// Returns `Some(t)` where `t` is the time `self + duration` if `t` can be represented as
@@ -133,7 +288,7 @@ impl Instant {
     /// otherwise.
     pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
         self.0
-            .checked_add((duration.as_nanos() as u64 as f64 / crate::nanos_per_cycle()) as u64)
+            .checked_add(crate::nanos_to_cycles(duration.as_nanos())?)
             .map(Instant)
     }
 
@@ -142,7 +297,7 @@ impl Instant {
     /// otherwise.
     pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
         self.0
-            .checked_sub((duration.as_nanos() as u64 as f64 / crate::nanos_per_cycle()) as u64)
+            .checked_sub(crate::nanos_to_cycles(duration.as_nanos())?)
             .map(Instant)
     }
 
@@ -156,6 +311,7 @@ impl Instant {
     /// # Examples
     ///
     /// ```
+    /// # #[cfg(feature = "std")] {
     /// use std::time::UNIX_EPOCH;
     /// use minstant::{Instant, Anchor};
     ///
@@ -164,16 +320,61 @@ impl Instant {
     ///
     /// let expected = UNIX_EPOCH.elapsed().unwrap().as_nanos();
     /// assert!((instant.as_unix_nanos(&anchor) as i64 - expected as i64).abs() < 1_000_000);
+    /// # }
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timestamp is before [UNIX_EPOCH](std::time::UNIX_EPOCH) or does not fit in
+    /// a `u64`. See [`checked_as_unix_nanos`](Instant::checked_as_unix_nanos) for a version
+    /// without panic.
     pub fn as_unix_nanos(&self, anchor: &Anchor) -> u64 {
+        self.checked_as_unix_nanos(anchor)
+            .expect("overflow when converting instant to unix time")
+    }
+
+    /// Convert interal clocking counter into a UNIX timestamp represented as the
+    /// nanoseconds elapsed from [UNIX_EPOCH](std::time::UNIX_EPOCH), or `None` if the
+    /// timestamp is before [UNIX_EPOCH](std::time::UNIX_EPOCH) or does not fit in a `u64`.
+    ///
+    /// *[See also the `Instant::as_unix_nanos()`](crate::Instant::as_unix_nanos).*
+    pub fn checked_as_unix_nanos(&self, anchor: &Anchor) -> Option<u64> {
         if self.0 > anchor.cycle {
-            let forward_ns = ((self.0 - anchor.cycle) as f64 * crate::nanos_per_cycle()) as u64;
-            anchor.unix_time_ns + forward_ns
+            let forward_ns = crate::cycles_to_nanos(self.0 - anchor.cycle);
+            u64::try_from(anchor.unix_time_ns as u128 + forward_ns).ok()
         } else {
-            let backward_ns = ((anchor.cycle - self.0) as f64 * crate::nanos_per_cycle()) as u64;
-            anchor.unix_time_ns - backward_ns
+            let backward_ns = crate::cycles_to_nanos(anchor.cycle - self.0);
+            u64::try_from((anchor.unix_time_ns as u128).checked_sub(backward_ns)?).ok()
         }
     }
+
+    /// Convert interal clocking counter into a timestamp of the kernel's perf events, using the
+    /// exact conversion published by the kernel.
+    ///
+    /// Returns `None` unless TSC is in use and was calibrated from perf events, see
+    /// [`FrequencySource::PerfEvent`](crate::FrequencySource::PerfEvent).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    ///
+    /// if let Some(nanos) = Instant::now().as_perf_nanos() {
+    ///     println!("perf timestamp: {}", nanos);
+    /// }
+    /// ```
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn as_perf_nanos(&self) -> Option<u64> {
+        crate::perf_nanos(self.0)
+    }
+}
+
+/// Converts nanoseconds into a `Duration`, or returns `None` if the seconds do not fit in a
+/// `u64`.
+fn duration_from_nanos(nanos: u128) -> Option<Duration> {
+    let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+    Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
 }
 
 impl Add<Duration> for Instant {
@@ -207,24 +408,182 @@ impl SubAssign<Duration> for Instant {
 }
 
 impl Sub<Instant> for Instant {
-    type Output = Duration;
+    type Output = CycleDuration;
 
-    /// Returns the amount of time elapsed from another instant to this one,
-    /// or zero duration if that instant is later than this one.
+    /// Returns the number of cycles elapsed from another instant to this one, or zero if that
+    /// instant is later than this one. Unlike [`std::time::Instant`], the span is not converted
+    /// into time, see [`CycleDuration::to_duration()`] and [`Instant::duration_since()`].
+    fn sub(self, other: Instant) -> CycleDuration {
+        self.cycles_since(other)
+    }
+}
+
+impl Add<CycleDuration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: CycleDuration) -> Instant {
+        self.0
+            .checked_add(other.0)
+            .map(Instant)
+            .expect("overflow when adding cycles to instant")
+    }
+}
+
+impl AddAssign<CycleDuration> for Instant {
+    fn add_assign(&mut self, other: CycleDuration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<CycleDuration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: CycleDuration) -> Instant {
+        self.0
+            .checked_sub(other.0)
+            .map(Instant)
+            .expect("overflow when subtracting cycles from instant")
+    }
+}
+
+impl SubAssign<CycleDuration> for Instant {
+    fn sub_assign(&mut self, other: CycleDuration) {
+        *self = *self - other;
+    }
+}
+
+impl core::fmt::Debug for Instant {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A raw value of the counter behind an [`Instant`], or a number of its cycles in a
+/// [`CycleDuration`].
+///
+/// The counter counts TSC cycles relative to an arbitrary origin, or nanoseconds when the
+/// fallback clock is used. It is only meaningful within the process that read it.
+///
+/// *[See also the `Instant::as_cycles()`](crate::Instant::as_cycles).*
+pub type Cycles = u64;
+
+/// A span between two [`Instant`]s, counted in cycles of the clock behind them.
+///
+/// Cycles are converted into time only on request, with the current calibration, so that hot
+/// loops can accumulate and compare spans without the conversion.
+///
+/// `Instant - Instant` returns a `CycleDuration`.
+///
+/// *[See also the `Instant::cycles_since()`](crate::Instant::cycles_since).*
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CycleDuration(u64);
+
+impl CycleDuration {
+    /// A span of zero cycles.
+    pub const ZERO: CycleDuration = CycleDuration(0);
+
+    /// Returns a span of `cycles` cycles.
+    #[inline]
+    pub const fn from_cycles(cycles: Cycles) -> CycleDuration {
+        CycleDuration(cycles)
+    }
+
+    /// Returns the number of cycles in this span.
+    #[inline]
+    pub const fn as_cycles(&self) -> Cycles {
+        self.0
+    }
+
+    /// Converts this span into time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::Instant;
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
+    ///
+    /// let start = Instant::now();
+    /// let end = Instant::now();
+    /// assert_eq!(end.cycles_since(start).to_duration(), end.duration_since(start));
+    /// ```
+    #[inline]
+    pub fn to_duration(&self) -> Duration {
+        duration_from_nanos(crate::cycles_to_nanos(self.0)).unwrap_or(Duration::MAX)
+    }
+
+    /// Converts time into a span of cycles.
     ///
     /// # Panics
     ///
-    /// Previously we panicked if `other` was later than `self`. Currently this method saturates
-    /// to follow the behavior of the standard library. Future versions may reintroduce the panic
-    /// in some circumstances.
-    fn sub(self, other: Instant) -> Duration {
-        self.duration_since(other)
+    /// Panics if the number of cycles does not fit in a `u64`. See
+    /// [`checked_from_duration`](CycleDuration::checked_from_duration) for a version without
+    /// panic.
+    #[inline]
+    pub fn from_duration(duration: Duration) -> CycleDuration {
+        CycleDuration::checked_from_duration(duration)
+            .expect("overflow when converting duration to cycles")
+    }
+
+    /// Converts time into a span of cycles, or returns `None` if the number of cycles does not
+    /// fit in a `u64`.
+    #[inline]
+    pub fn checked_from_duration(duration: Duration) -> Option<CycleDuration> {
+        crate::nanos_to_cycles(duration.as_nanos()).map(CycleDuration)
+    }
+
+    /// Returns `Some(self + other)`, or `None` if it overflows.
+    #[inline]
+    pub fn checked_add(&self, other: CycleDuration) -> Option<CycleDuration> {
+        self.0.checked_add(other.0).map(CycleDuration)
+    }
+
+    /// Returns `Some(self - other)`, or `None` if `other` is longer than `self`.
+    #[inline]
+    pub fn checked_sub(&self, other: CycleDuration) -> Option<CycleDuration> {
+        self.0.checked_sub(other.0).map(CycleDuration)
+    }
+
+    /// Returns `self - other`, or zero if `other` is longer than `self`.
+    #[inline]
+    pub fn saturating_sub(&self, other: CycleDuration) -> CycleDuration {
+        CycleDuration(self.0.saturating_sub(other.0))
     }
 }
 
-impl std::fmt::Debug for Instant {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+impl Add for CycleDuration {
+    type Output = CycleDuration;
+
+    fn add(self, other: CycleDuration) -> CycleDuration {
+        self.checked_add(other)
+            .expect("overflow when adding cycle durations")
+    }
+}
+
+impl AddAssign for CycleDuration {
+    fn add_assign(&mut self, other: CycleDuration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for CycleDuration {
+    type Output = CycleDuration;
+
+    fn sub(self, other: CycleDuration) -> CycleDuration {
+        self.checked_sub(other)
+            .expect("overflow when subtracting cycle durations")
+    }
+}
+
+impl SubAssign for CycleDuration {
+    fn sub_assign(&mut self, other: CycleDuration) {
+        *self = *self - other;
+    }
+}
+
+impl core::iter::Sum for CycleDuration {
+    fn sum<I: Iterator<Item = CycleDuration>>(iter: I) -> CycleDuration {
+        iter.fold(CycleDuration::ZERO, Add::add)
     }
 }
 
@@ -237,6 +596,8 @@ pub struct Anchor {
     cycle: u64,
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
 impl Default for Anchor {
     fn default() -> Self {
         Self::new()
@@ -245,23 +606,50 @@ impl Default for Anchor {
 
 impl Anchor {
     #[inline]
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
     pub fn new() -> Anchor {
-        let unix_time_ns = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("unexpected time drift")
-            .as_nanos() as u64;
+        // Calibrate first, so that it does not run between the two readings.
+        crate::init();
+        let unix_time_ns = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .ok()
+            .and_then(|duration| u64::try_from(duration.as_nanos()).ok())
+            .expect("unexpected time drift");
         Anchor {
             unix_time_ns,
             cycle: crate::current_cycle(),
         }
     }
+
+    /// Returns an anchor tying `instant` to a UNIX timestamp represented as the nanoseconds
+    /// elapsed from [UNIX_EPOCH](std::time::UNIX_EPOCH), e.g. one obtained from a hardware
+    /// clock where `std` is unavailable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use minstant::{Anchor, Instant};
+    /// # #[cfg(not(feature = "std"))]
+    /// # minstant::set_cycles_per_second(1_000_000_000);
+    ///
+    /// let now = Instant::now();
+    /// let anchor = Anchor::from_unix_nanos(now, 1_700_000_000_000_000_000);
+    /// assert_eq!(now.as_unix_nanos(&anchor), 1_700_000_000_000_000_000);
+    /// ```
+    pub fn from_unix_nanos(instant: Instant, unix_nanos: u64) -> Anchor {
+        Anchor {
+            unix_time_ns: unix_nanos,
+            cycle: instant.0,
+        }
+    }
 }
 
 #[cfg(all(feature = "atomic", target_has_atomic = "64"))]
 #[cfg_attr(docsrs, doc(cfg(all(feature = "atomic", target_has_atomic = "64"))))]
 mod atomic {
     use super::Instant;
-    use std::sync::atomic::{AtomicU64, Ordering};
+    use core::sync::atomic::{AtomicU64, Ordering};
     #[cfg(doc)]
     use Ordering::*;
 
@@ -376,3 +764,110 @@ mod atomic {
 #[cfg(all(feature = "atomic", target_has_atomic = "64"))]
 #[cfg_attr(docsrs, doc(cfg(all(feature = "atomic", target_has_atomic = "64"))))]
 pub use atomic::Atomic;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// The error allowed by a round trip: one cycle, plus rounding of the nanoseconds.
+    /// Without `std`, the frequency has to be set before instants are converted. Other tests
+    /// convert concurrently, so every test sets the same one.
+    fn set_frequency() {
+        #[cfg(not(feature = "std"))]
+        crate::set_cycles_per_second(crate::mult_shift::NANOS_PER_SEC);
+    }
+
+    fn tolerance(duration: Duration) -> u128 {
+        crate::cycles_to_nanos(1) + 2 + (duration.as_nanos() >> 62)
+    }
+
+    fn duration() -> impl Strategy<Value = Duration> {
+        prop_oneof![
+            (0u64..1 << 40).prop_map(Duration::from_nanos),
+            (any::<u64>(), 0u32..1_000_000_000)
+                .prop_map(|(secs, nanos)| Duration::new(secs, nanos)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_add_then_duration_since(cycles: u64, duration in duration()) {
+            set_frequency();
+            let instant = Instant(cycles);
+            match instant.checked_add(duration) {
+                Some(later) => {
+                    let elapsed = later.checked_duration_since(instant).unwrap();
+                    prop_assert!(elapsed.as_nanos().abs_diff(duration.as_nanos()) <= tolerance(duration));
+                }
+                None => {
+                    let max = Instant(u64::MAX).duration_since(instant);
+                    prop_assert!(duration.as_nanos() + tolerance(duration) > max.as_nanos());
+                }
+            }
+        }
+
+        #[test]
+        fn test_sub_then_duration_since(cycles: u64, duration in duration()) {
+            set_frequency();
+            let instant = Instant(cycles);
+            match instant.checked_sub(duration) {
+                Some(earlier) => {
+                    let elapsed = instant.checked_duration_since(earlier).unwrap();
+                    prop_assert!(elapsed.as_nanos().abs_diff(duration.as_nanos()) <= tolerance(duration));
+                }
+                None => {
+                    let max = instant.duration_since(Instant(0));
+                    prop_assert!(duration.as_nanos() + tolerance(duration) > max.as_nanos());
+                }
+            }
+        }
+
+        #[test]
+        fn test_cycle_duration(start: u64, end: u64, duration in duration()) {
+            set_frequency();
+            let (start, end) = (Instant(start), Instant(end));
+            let cycles = end - start;
+            prop_assert_eq!(cycles, end.cycles_since(start));
+            prop_assert_eq!(cycles.to_duration(), end.duration_since(start));
+            prop_assert_eq!(start + cycles, if end >= start { end } else { start });
+            prop_assert_eq!(Instant::from_cycles(end.as_cycles()), end);
+
+            if let Some(cycles) = CycleDuration::checked_from_duration(duration) {
+                let nanos = cycles.to_duration().as_nanos();
+                prop_assert!(nanos.abs_diff(duration.as_nanos()) <= tolerance(duration));
+            }
+        }
+
+        #[test]
+        fn test_checked_as_unix_nanos(a: u64, b: u64, anchor_cycle: u64, unix_time_ns: u64) {
+            set_frequency();
+            let anchor = Anchor::from_unix_nanos(Instant(anchor_cycle), unix_time_ns);
+            prop_assert_eq!(Instant(anchor_cycle).checked_as_unix_nanos(&anchor), Some(unix_time_ns));
+
+            let (earlier, later) = (Instant(a.min(b)), Instant(a.max(b)));
+            let earlier_nanos = earlier.checked_as_unix_nanos(&anchor);
+            let later_nanos = later.checked_as_unix_nanos(&anchor);
+            match (earlier_nanos, later_nanos) {
+                (Some(earlier_nanos), Some(later_nanos)) => {
+                    prop_assert!(earlier_nanos <= later_nanos);
+                }
+                // Past the anchor, a timestamp that does not fit stays out of range later on.
+                (None, Some(_)) => prop_assert!(earlier.0 < anchor_cycle),
+                // Before the anchor, a timestamp before UNIX_EPOCH stays so earlier on.
+                (Some(_), None) => prop_assert!(later.0 > anchor_cycle),
+                (None, None) => {}
+            }
+            for (instant, nanos) in [(earlier, earlier_nanos), (later, later_nanos)] {
+                if let Some(nanos) = nanos {
+                    if instant.0 >= anchor_cycle {
+                        prop_assert!(nanos >= unix_time_ns);
+                    }
+                    if instant.0 <= anchor_cycle {
+                        prop_assert!(nanos <= unix_time_ns);
+                    }
+                }
+            }
+        }
+    }
+}
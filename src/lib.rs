@@ -6,6 +6,8 @@
 //! ## Example
 //!
 //! ```
+//! # #[cfg(not(feature = "std"))]
+//! # minstant::set_cycles_per_second(1_000_000_000);
 //! let start = minstant::Instant::now();
 //!
 //! // Code snipppet to measure
@@ -13,10 +15,15 @@
 //! let duration: std::time::Duration = start.elapsed();
 //! ```
 //!
+//! Unlike `std::time::Instant`, `Instant - Instant` returns a [`CycleDuration`], which is
+//! converted into time with [`CycleDuration::to_duration()`].
+//!
 //! ## Platform Support
 //!
 //! Currently, only the Linux on `x86` or `x86_64` is backed by [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter).
-//! On other platforms, `minstant` falls back to coarse time.
+//! On other platforms, `minstant` falls back to the monotonic clock of the platform, or to coarse
+//! time with the `fallback-coarse` feature. On Linux, the fallback clock can also be selected with
+//! the `MINSTANT_FALLBACK_CLOCK` environment variable, see [`FallbackClock`].
 //!
 //! ## Calibration
 //!
@@ -27,25 +34,141 @@
 //!
 //! This calibration is stored globally and reused. In order to start the calibration before any call to `minstant`
 //! as to make sure that the time spent on `minstant` is constant, we link the calibration into application's
-//! initialization linker section, so it'll get executed once the process starts.
+//! initialization linker section, so it'll get executed once the process starts. Long-running
+//! processes can refresh it later with [`recalibrate()`](crate::recalibrate).
+//!
+//! The `no-ctor` feature opts out of the initialization linker section: the calibration then runs on
+//! first use, or when [`init()`](crate::init) is called. The `background-calibration` feature moves the
+//! calibration to a background thread, while [`Instant::now()`](crate::Instant::now) uses a provisional
+//! frequency in the meantime.
+//!
+//! The `per-cpu-offsets` feature corrects TSCs that are not synchronized across CPUs, e.g. across
+//! the sockets of some machines: the offset of every CPU is measured at calibration, and
+//! [`Instant::now()`](crate::Instant::now) reads TSC with `rdtscp` to find out which CPU's offset
+//! to apply. Like the check of `MINSTANT_MAX_CROSS_CORE_SKEW_NS`, the measurement pins two threads
+//! to every CPU in turn, which happens before `main` runs unless `no-ctor` defers the calibration.
+//!
+//! The `assume-tsc` feature is meant for builds that only run on hardware known to have a usable
+//! TSC: [`Instant::now()`](crate::Instant::now) then reads TSC without checking whether it is
+//! available, and the process aborts at startup if it is not. TSC is never demoted at runtime
+//! either. `Instant::now()` must then not be called from other initializers that may run before
+//! the calibration, which still runs at startup if `no-ctor` is enabled as well.
+//!
+//! ## `no_std`
+//!
+//! Without the default `std` feature, `minstant` is `no_std`: [`Instant`], [`Anchor`] and `Atomic`
+//! keep their arithmetic, but TSC is neither checked nor calibrated. `Instant::now()` reads TSC
+//! directly on `x86` and `x86_64`, and the application supplies its frequency with
+//! `set_cycles_per_second()`. Anchors are created with
+//! [`Anchor::from_unix_nanos()`](crate::Anchor::from_unix_nanos).
 //!
 //! *[See also the `Instant` type](crate::Instant).*
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+#[cfg(all(
+    feature = "assume-tsc",
+    not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))
+))]
+compile_error!("the `assume-tsc` feature is only supported on Linux on `x86` or `x86_64`");
+
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
+mod cache;
+#[cfg(feature = "std")]
+mod calibration;
+#[cfg(feature = "std")]
+mod clock_info;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+mod cross_core;
+#[cfg(feature = "std")]
+mod drift;
+#[cfg(any(
+    not(feature = "std"),
+    all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))
+))]
+mod factors;
+#[cfg(feature = "std")]
+mod fallback;
 mod instant;
-#[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+mod mult_shift;
+#[cfg(not(feature = "std"))]
+mod nostd;
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
+mod perf_event;
+#[cfg(all(
+    feature = "std",
+    target_os = "linux",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
 mod tsc_now;
+#[cfg(feature = "std")]
+mod watcher;
 
+#[cfg(feature = "std")]
+pub use calibration::{Calibration, FrequencySource};
+#[cfg(feature = "std")]
+pub use clock_info::{ClockInfo, ClockSource, FallbackClock, TscUnavailableReason};
+#[cfg(feature = "std")]
+pub use config::{CalibrationTimeout, ClockPreference, Config, ReferenceClock, TscPolicy};
+#[cfg(feature = "std")]
+pub use cross_core::{CpuOffset, CrossCoreSkew};
+#[cfg(feature = "std")]
+pub use drift::{Drift, DriftMonitor, DriftMonitorHandle};
 #[cfg(all(feature = "atomic", target_has_atomic = "64"))]
 #[cfg_attr(docsrs, doc(cfg(all(feature = "atomic", target_has_atomic = "64"))))]
 pub use instant::Atomic;
-pub use instant::{Anchor, Instant};
+pub use instant::{Anchor, CycleDuration, Cycles, Instant};
+#[cfg(all(not(feature = "std"), any(target_arch = "x86", target_arch = "x86_64")))]
+pub(crate) use nostd::current_cycle;
+#[cfg(not(feature = "std"))]
+#[cfg_attr(docsrs, doc(cfg(not(feature = "std"))))]
+pub use nostd::set_cycles_per_second;
+#[cfg(not(feature = "std"))]
+pub(crate) use nostd::{cycles_to_nanos, nanos_to_cycles};
+#[cfg(feature = "std")]
+pub use watcher::ClocksourceWatcher;
+
+/// Calibrate [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) now, unless it has been done
+/// already.
+///
+/// The calibration runs at process startup by default, so this is only useful with the `no-ctor`
+/// feature, where it would otherwise run on the first call to `minstant`. Calling it early keeps
+/// the cost of the calibration out of the first measurement.
+///
+/// # Examples
+///
+/// ```
+/// minstant::init();
+/// let start = minstant::Instant::now();
+/// ```
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn init() {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::init()
+    }
+}
 
 /// Return `true` if the current platform supports [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter),
 /// and the calibration has succeed.
 ///
-/// The result is always the same during the lifetime of the application process.
+/// The result is always the same during the lifetime of the application process, unless TSC is
+/// demoted at runtime by [`check_clocksource()`](crate::check_clocksource), in which case it turns
+/// `false` for good.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
 #[inline]
 pub fn is_tsc_available() -> bool {
     #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
@@ -58,14 +181,210 @@ pub fn is_tsc_available() -> bool {
     }
 }
 
+/// Return the reason why [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) is unavailable,
+/// or `None` if TSC is available.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(reason) = minstant::tsc_unavailable_reason() {
+///     println!("falling back from TSC: {}", reason);
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn tsc_unavailable_reason() -> Option<TscUnavailableReason> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::tsc_unavailable_reason()
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        Some(TscUnavailableReason::UnsupportedArch)
+    }
+}
+
+/// Check whether the kernel still trusts [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter), and
+/// switch to the fallback clock if it does not. Returns whether TSC is still available.
+///
+/// The kernel's clocksource watchdog may mark TSC as unstable long after the process started.
+/// This function re-reads the kernel clock sources, and if TSC has been removed from them, or the
+/// kernel switched its current clock source away from TSC, [`Instant::now()`](crate::Instant::now)
+/// transitions to the fallback clock. Instants stay monotonic across the switch.
+///
+/// *[See also the `ClocksourceWatcher`](crate::ClocksourceWatcher) which calls this periodically.*
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn check_clocksource() -> bool {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::check_clocksource()
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        false
+    }
+}
+
+/// Measure the frequency of [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) again and
+/// atomically publish the new conversion factors.
+///
+/// Long-running processes can call this periodically to compensate for a calibration that was
+/// skewed at startup. Concurrent calls to [`Instant::now()`](crate::Instant::now) never observe a
+/// partially updated state, and instants taken before the recalibration stay comparable and
+/// monotonic with the ones taken after it.
+///
+/// Returns `None` if TSC is not available, in which case there is nothing to recalibrate, or if
+/// the new calibration diverged, in which case the previous one is kept.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn recalibrate() -> Option<Calibration> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::recalibrate()
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        None
+    }
+}
+
+/// Measure the offsets between the [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter)s of
+/// the CPUs the process may run on.
+///
+/// The calibration assumes that TSCs are synchronized across CPUs as long as the kernel trusts
+/// them. This verifies it by pinning a thread to every CPU in the affinity mask of the calling
+/// thread in turn, and exchanging TSC readings with a thread pinned to the first CPU. The
+/// calling thread itself is not pinned. With many CPUs, this takes a while.
+///
+/// If the skew exceeds [`Config::max_cross_core_skew`](crate::Config::max_cross_core_skew),
+/// [`Instant::now()`](crate::Instant::now) switches to the fallback clock, the same way it does
+/// in [`check_clocksource()`](crate::check_clocksource).
+///
+/// Returns an error of kind [`Unsupported`](std::io::ErrorKind::Unsupported) on platforms
+/// without TSC support or if TSC was never calibrated, or another error if a thread could not
+/// be pinned to a CPU, or did not answer in time.
+///
+/// # Examples
+///
+/// ```
+/// if let Ok(skew) = minstant::verify_cross_core() {
+///     println!("tsc skew across cpus: {:?}", skew.max_skew);
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn verify_cross_core() -> std::io::Result<CrossCoreSkew> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::verify_cross_core()
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Return a report describing how the clock was set up: the active clock source, and the
+/// outcome of the latest [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter) calibration.
+///
+/// This is meant to be logged at startup or exported for auditing.
+///
+/// # Examples
+///
+/// ```
+/// let info = minstant::clock_info();
+/// println!("{:?}", info.source);
+/// if let Some(calibration) = info.calibration {
+///     println!("TSC runs at {} Hz", calibration.cycles_per_second());
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn clock_info() -> ClockInfo {
+    let source = if is_tsc_available() {
+        ClockSource::Tsc
+    } else {
+        ClockSource::Fallback(fallback::clock())
+    };
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    let calibration = tsc_now::calibration();
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    let calibration = None;
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    let reference_clock = tsc_now::reference_clock();
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    let reference_clock = config::config().reference_clock;
+
+    ClockInfo {
+        source,
+        calibration,
+        tsc_unavailable_reason: tsc_unavailable_reason(),
+        tsc_policy: config::config().tsc_policy,
+        reference_clock,
+        config: config::config().clone(),
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn correct_rate(rate_error: f64) -> Option<Calibration> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::correct_rate(rate_error)
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        let _ = rate_error;
+        None
+    }
+}
+
+/// Reads the clock TSC is calibrated against, as the time elapsed since an arbitrary origin.
+#[cfg(feature = "std")]
+pub(crate) fn reference_now() -> std::time::Duration {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::reference_now()
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        static ORIGIN: std::sync::OnceLock<web_time::Instant> = std::sync::OnceLock::new();
+        ORIGIN.get_or_init(web_time::Instant::now).elapsed()
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn perf_nanos(cycle: u64) -> Option<u64> {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::perf_nanos(cycle)
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        let _ = cycle;
+        None
+    }
+}
+
+#[cfg(feature = "std")]
 #[inline]
 pub(crate) fn current_cycle() -> u64 {
-    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    #[cfg(feature = "assume-tsc")]
+    {
+        tsc_now::current_cycle()
+    }
+    #[cfg(all(
+        not(feature = "assume-tsc"),
+        target_os = "linux",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
     {
         if tsc_now::is_tsc_available() {
             tsc_now::current_cycle()
         } else {
-            current_cycle_fallback()
+            tsc_now::current_cycle_fallback()
         }
     }
     #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
@@ -74,45 +393,114 @@ pub(crate) fn current_cycle() -> u64 {
     }
 }
 
-#[cfg(not(feature = "fallback-coarse"))]
-pub(crate) fn current_cycle_fallback() -> u64 {
-    web_time::SystemTime::now()
-        .duration_since(web_time::UNIX_EPOCH)
-        .map(|d| d.as_nanos() as u64)
-        .unwrap_or(0)
+/// How a read of TSC is ordered relative to the surrounding instructions.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TscOrdering {
+    /// `lfence; rdtsc`: TSC is read once all earlier instructions completed.
+    Fenced,
+    /// `rdtscp`: TSC is read once all earlier instructions completed, but later
+    /// instructions may start before.
+    Rdtscp,
+    /// `cpuid; rdtsc`: the start of a measured region.
+    Start,
+    /// `rdtscp; cpuid`: the end of a measured region.
+    Stop,
+}
+
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn current_cycle_ordered(ordering: TscOrdering) -> u64 {
+    #[cfg(feature = "assume-tsc")]
+    {
+        tsc_now::current_cycle_ordered(ordering)
+    }
+    #[cfg(all(
+        not(feature = "assume-tsc"),
+        target_os = "linux",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ))]
+    {
+        if tsc_now::is_tsc_available() {
+            tsc_now::current_cycle_ordered(ordering)
+        } else {
+            tsc_now::current_cycle_fallback()
+        }
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        let _ = ordering;
+        current_cycle_fallback()
+    }
 }
 
-#[cfg(feature = "fallback-coarse")]
+#[cfg(feature = "std")]
+#[inline]
 pub(crate) fn current_cycle_fallback() -> u64 {
-    let coarse = coarsetime::Instant::now_without_cache_update();
-    coarsetime::Duration::from_ticks(coarse.as_ticks()).as_nanos()
+    fallback::now()
+}
+
+/// Converts a number of cycles into nanoseconds.
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn cycles_to_nanos(cycles: u64) -> u128 {
+    #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
+    {
+        tsc_now::nanos_factor().apply(cycles)
+    }
+    #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
+    {
+        cycles as u128
+    }
 }
 
+/// Converts a number of nanoseconds into cycles, or returns `None` if the result
+/// does not fit in a `u64`.
+#[cfg(feature = "std")]
 #[inline]
-pub(crate) fn nanos_per_cycle() -> f64 {
+pub(crate) fn nanos_to_cycles(nanos: u128) -> Option<u64> {
     #[cfg(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64")))]
     {
-        tsc_now::nanos_per_cycle()
+        tsc_now::cycles_factor().checked_apply(nanos)
     }
     #[cfg(not(all(target_os = "linux", any(target_arch = "x86", target_arch = "x86_64"))))]
     {
-        1.0
+        u64::try_from(nanos).ok()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
     use rand::Rng;
     use std::time::{Duration, Instant as StdInstant};
     use wasm_bindgen_test::wasm_bindgen_test;
 
+    #[test]
+    fn test_init() {
+        let handles: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(|| (Instant::now(), is_tsc_available())))
+            .collect();
+        init();
+        for handle in handles {
+            let (instant, tsc_available) = handle.join().unwrap();
+            assert!(instant <= Instant::now());
+            assert_eq!(tsc_available, is_tsc_available());
+        }
+    }
+
     #[test]
     #[wasm_bindgen_test]
     fn test_is_tsc_available() {
         let _ = is_tsc_available();
     }
 
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_tsc_unavailable_reason() {
+        assert_eq!(tsc_unavailable_reason().is_none(), is_tsc_available());
+    }
+
     #[test]
     #[wasm_bindgen_test]
     fn test_monotonic() {
@@ -126,8 +514,136 @@ mod tests {
 
     #[test]
     #[wasm_bindgen_test]
-    fn test_nanos_per_cycle() {
-        let _ = nanos_per_cycle();
+    fn test_fallback_monotonic() {
+        let mut prev = 0;
+        for _ in 0..10000 {
+            let cur = current_cycle_fallback();
+            assert!(cur >= prev);
+            prev = cur;
+        }
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_ordered_monotonic() {
+        let mut prev = 0;
+        for ordering in [
+            TscOrdering::Start,
+            TscOrdering::Fenced,
+            TscOrdering::Rdtscp,
+            TscOrdering::Stop,
+        ]
+        .iter()
+        .cycle()
+        .take(10000)
+        {
+            let cur = current_cycle_ordered(*ordering);
+            assert!(cur >= prev, "{:?}", ordering);
+            prev = cur;
+        }
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_cycles_to_nanos() {
+        assert_eq!(cycles_to_nanos(0), 0);
+        let cycles = nanos_to_cycles(1_000_000_000).unwrap();
+        let nanos = cycles_to_nanos(cycles) as i128;
+        assert!((nanos - 1_000_000_000).abs() <= 1, "nanos: {}", nanos);
+    }
+
+    #[test]
+    #[cfg(feature = "assume-tsc")]
+    fn test_assume_tsc() {
+        assert!(is_tsc_available());
+        assert_eq!(clock_info().source, ClockSource::Tsc);
+        assert!(!check_clocksource() || is_tsc_available());
+    }
+
+    #[test]
+    fn test_recalibrate() {
+        let before = Instant::now();
+        match recalibrate() {
+            Some(calibration) => {
+                assert!(is_tsc_available());
+                assert!(calibration.cycles_per_second() > 0);
+                let nanos = cycles_to_nanos(calibration.cycles_per_second()) as i128;
+                assert!((nanos - 1_000_000_000).abs() <= 1, "nanos: {}", nanos);
+            }
+            None => assert!(!is_tsc_available()),
+        }
+        assert!(Instant::now() >= before);
+    }
+
+    #[test]
+    #[wasm_bindgen_test]
+    fn test_clock_info() {
+        let info = clock_info();
+        assert_eq!(info.source == ClockSource::Tsc, is_tsc_available());
+        if let Some(calibration) = info.calibration {
+            if matches!(
+                calibration.frequency_source(),
+                FrequencySource::Measured | FrequencySource::Cpuid
+            ) {
+                assert!(calibration.iterations() > 0);
+            }
+            assert!(calibration.relative_error() >= 0.0);
+        }
+        assert_eq!(info.tsc_policy, info.config.tsc_policy());
+        if std::env::var_os("MINSTANT_REFERENCE_CLOCK").is_none() {
+            assert_eq!(info.reference_clock, ReferenceClock::MonotonicRaw);
+        }
+        if std::env::var_os("MINSTANT_FALLBACK_CLOCK").is_none() {
+            assert_eq!(info.config.fallback_clock(), fallback::clock());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "background-calibration")]
+    fn test_background_calibration() {
+        let before = Instant::now();
+        while clock_info().calibration.is_some_and(|calibration| {
+            calibration.frequency_source() == FrequencySource::Provisional
+        }) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let after = Instant::now();
+        assert!(before <= after);
+        assert!(after.duration_since(before) < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_clocksource_watcher() {
+        let watcher = ClocksourceWatcher::spawn(Duration::from_millis(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        drop(watcher);
+        assert_eq!(check_clocksource(), is_tsc_available());
+    }
+
+    #[test]
+    fn test_verify_cross_core() {
+        match verify_cross_core() {
+            Ok(skew) => {
+                assert_eq!(skew.offsets[0].offset_cycles, 0);
+                for offset in &skew.offsets {
+                    assert!(offset.offset_cycles.unsigned_abs() <= skew.max_skew_cycles);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+                assert!(clock_info().calibration.is_none())
+            }
+            // Restricted runners may forbid pinning threads, or starve them until the timeout.
+            Err(_) => {}
+        }
+    }
+
+    #[test]
+    fn test_drift_monitor() {
+        let mut monitor = DriftMonitor::new().max_skew(Duration::from_secs(1));
+        std::thread::sleep(Duration::from_millis(100));
+        let drift = monitor.poll();
+        assert!(drift.elapsed >= Duration::from_millis(100));
+        assert!(drift.skew_nanos.abs() < 5_000_000, "drift: {:?}", drift);
     }
 
     #[test]
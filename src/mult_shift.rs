@@ -0,0 +1,41 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! The fixed-point ratios converting between cycles and nanoseconds.
+
+pub(crate) const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A fixed-point ratio `mult / 2^shift`, like the kernel's clocksource.
+///
+/// `mult` is normalized to have its top bit set, so the ratio is exact to within
+/// 2^-64 relative, and converting any `u64` stays accurate to a few units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MultShift {
+    pub(crate) mult: u64,
+    pub(crate) shift: u32,
+}
+
+impl MultShift {
+    /// Returns the rounded fixed-point representation of `numerator / denominator`.
+    pub(crate) fn new(numerator: u64, denominator: u64) -> MultShift {
+        let numerator = numerator.max(1) as u128;
+        let denominator = denominator.max(1) as u128;
+
+        // The ratio is at least 2^-64, so `mult` reaches 2^63 before the shift exceeds 127,
+        // and `numerator << shift` cannot overflow since `numerator` is below 2^64.
+        let mut shift = 0;
+        let mut mult = (numerator + denominator / 2) / denominator;
+        while shift < 127 && mult < 1 << 63 && numerator.leading_zeros() > shift {
+            let next = ((numerator << (shift + 1)) + denominator / 2) / denominator;
+            if next > u64::MAX as u128 {
+                break;
+            }
+            shift += 1;
+            mult = next;
+        }
+
+        MultShift {
+            mult: mult.min(u64::MAX as u128) as u64,
+            shift,
+        }
+    }
+}
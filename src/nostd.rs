@@ -0,0 +1,108 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! This module will be compiled when the `std` feature is disabled.
+//!
+//! Without `std`, TSC is neither checked nor calibrated: [`Instant::now()`](crate::Instant::now)
+//! reads the counter directly, and the frequency converting it into time is supplied by the
+//! application through [`set_cycles_per_second()`]. Converting instants into time panics until
+//! it is, rather than assuming a frequency.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::factors::AtomicFactors;
+use crate::mult_shift::{MultShift, NANOS_PER_SEC};
+
+static FACTORS: AtomicFactors = AtomicFactors::new();
+
+/// Serializes the stores to [`FACTORS`].
+static WRITER: AtomicBool = AtomicBool::new(false);
+
+/// Set once [`FACTORS`] holds the frequency supplied by the application.
+static IS_FREQUENCY_SET: AtomicBool = AtomicBool::new(false);
+
+/// Set the frequency of the counter read by [`Instant::now()`](crate::Instant::now), in cycles
+/// per second. It must be set before instants are converted into time, e.g. by
+/// [`Instant::duration_since()`](crate::Instant::duration_since), which panics otherwise.
+///
+/// This is only available without the `std` feature, where the frequency cannot be calibrated
+/// against a clock of the operating system. Durations computed from instants taken before and
+/// after a call are converted with the new frequency.
+///
+/// # Panics
+///
+/// Panics if `cycles_per_second` is zero.
+///
+/// # Examples
+///
+/// ```
+/// // E.g. the TSC frequency reported by the runtime of a kernel-bypass framework.
+/// minstant::set_cycles_per_second(2_100_000_000);
+///
+/// let start = minstant::Instant::from_cycles(0);
+/// let end = minstant::Instant::from_cycles(2_100_000_000);
+/// let elapsed = end.duration_since(start);
+/// assert!(elapsed.abs_diff(core::time::Duration::from_secs(1)).as_nanos() <= 1);
+/// ```
+pub fn set_cycles_per_second(cycles_per_second: u64) {
+    assert!(cycles_per_second > 0, "the frequency must not be zero");
+    let nanos = MultShift::new(NANOS_PER_SEC, cycles_per_second);
+    let cycles = MultShift::new(cycles_per_second, NANOS_PER_SEC);
+
+    while WRITER
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    FACTORS.store(nanos, cycles);
+    IS_FREQUENCY_SET.store(true, Ordering::Release);
+    WRITER.store(false, Ordering::Release);
+}
+
+/// Returns the factors converting (1) cycles into nanoseconds and (2) nanoseconds into cycles.
+#[inline]
+fn factors() -> (MultShift, MultShift) {
+    assert!(
+        IS_FREQUENCY_SET.load(Ordering::Acquire),
+        "minstant: set_cycles_per_second() must be called before converting instants into time"
+    );
+    FACTORS.load()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+pub(crate) fn current_cycle() -> u64 {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::_rdtsc;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::_rdtsc;
+
+    unsafe { _rdtsc() }
+}
+
+/// Converts a number of cycles into nanoseconds.
+#[inline]
+pub(crate) fn cycles_to_nanos(cycles: u64) -> u128 {
+    factors().0.apply(cycles)
+}
+
+/// Converts a number of nanoseconds into cycles, or returns `None` if the result
+/// does not fit in a `u64`.
+#[inline]
+pub(crate) fn nanos_to_cycles(nanos: u128) -> Option<u64> {
+    factors().1.checked_apply(nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_cycles_per_second() {
+        // Other tests convert instants concurrently, so the conversion must stay the same.
+        set_cycles_per_second(NANOS_PER_SEC);
+        assert_eq!(cycles_to_nanos(2_000_000_000), 2_000_000_000);
+        assert_eq!(nanos_to_cycles(2_000_000_000), Some(2_000_000_000));
+        assert_eq!(nanos_to_cycles(u128::MAX), None);
+    }
+}
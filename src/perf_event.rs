@@ -0,0 +1,140 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! This module will be compiled when it's either linux_x86 or linux_x86_64.
+//!
+//! The kernel publishes its own conversion from TSC cycles into nanoseconds in the
+//! metadata page of every perf event, so that user space can convert the cycles it
+//! reads with `rdtsc` into perf timestamps. A software event monitoring the calling
+//! thread in user space is enough to get the page, which the default
+//! `perf_event_paranoid` allows to unprivileged processes.
+
+use std::mem;
+use std::ptr::{self, addr_of};
+use std::sync::atomic::{fence, Ordering};
+
+use crate::calibration::KernelClock;
+use crate::mult_shift::MultShift;
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_DUMMY: u64 = 9;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+
+const CAP_USER_TIME: u64 = 1 << 3;
+const CAP_USER_TIME_ZERO: u64 = 1 << 4;
+
+/// The first fields of `struct perf_event_attr`, i.e. `PERF_ATTR_SIZE_VER0`.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// The first fields of `struct perf_event_mmap_page`.
+#[repr(C)]
+struct PerfEventMmapPage {
+    version: u32,
+    compat_version: u32,
+    lock: u32,
+    index: u32,
+    offset: i64,
+    time_enabled: u64,
+    time_running: u64,
+    capabilities: u64,
+    pmc_width: u16,
+    time_shift: u16,
+    time_mult: u32,
+    time_offset: u64,
+    time_zero: u64,
+}
+
+/// Returns the kernel's conversion from TSC cycles into nanoseconds, or `None` if
+/// perf events are not permitted, or if the kernel does not let user space convert
+/// cycles into time, e.g. because it does not trust TSC.
+pub(crate) fn kernel_clock() -> Option<KernelClock> {
+    let attr = PerfEventAttr {
+        kind: PERF_TYPE_SOFTWARE,
+        size: mem::size_of::<PerfEventAttr>() as u32,
+        config: PERF_COUNT_SW_DUMMY,
+        // A dummy event never counts, so it is left enabled to keep the page updated.
+        flags: ATTR_EXCLUDE_KERNEL | ATTR_EXCLUDE_HV,
+        ..Default::default()
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            0 as libc::pid_t,
+            -1 as libc::c_int,
+            -1 as libc::c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as libc::c_int;
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let page = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            page_size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    let clock = if page == libc::MAP_FAILED {
+        None
+    } else {
+        let clock = unsafe { read_page(page as *const PerfEventMmapPage) };
+        unsafe { libc::munmap(page, page_size) };
+        clock
+    };
+    unsafe { libc::close(fd) };
+    clock
+}
+
+/// Reads the time fields of the page, retrying while the kernel updates them.
+///
+/// # Safety
+///
+/// `page` must point to the mapped metadata page of a perf event.
+unsafe fn read_page(page: *const PerfEventMmapPage) -> Option<KernelClock> {
+    loop {
+        let seq = ptr::read_volatile(addr_of!((*page).lock));
+        fence(Ordering::Acquire);
+        let capabilities = ptr::read_volatile(addr_of!((*page).capabilities));
+        let time_shift = ptr::read_volatile(addr_of!((*page).time_shift));
+        let time_mult = ptr::read_volatile(addr_of!((*page).time_mult));
+        let time_zero = ptr::read_volatile(addr_of!((*page).time_zero));
+        fence(Ordering::Acquire);
+        if ptr::read_volatile(addr_of!((*page).lock)) != seq {
+            continue;
+        }
+
+        if capabilities & CAP_USER_TIME == 0 || time_mult == 0 || time_shift >= 64 {
+            return None;
+        }
+        return Some(KernelClock {
+            factor: MultShift {
+                mult: time_mult as u64,
+                shift: time_shift as u32,
+            },
+            time_zero: (capabilities & CAP_USER_TIME_ZERO != 0).then_some(time_zero),
+        });
+    }
+}
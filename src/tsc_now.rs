@@ -2,149 +2,1187 @@
 
 //! This module will be compiled when it's either linux_x86 or linux_x86_64.
 
-use std::time::Instant;
-use std::{cell::UnsafeCell, fs::read_to_string};
+#[cfg(target_arch = "x86")]
+use core::arch::x86::{__cpuid, CpuidResult};
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::{__cpuid, CpuidResult};
+use std::fs::read_to_string;
+use std::io;
+use std::ops::Sub;
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::Duration;
+
+use crate::calibration::KernelClock;
+use crate::config::config;
+use crate::factors::AtomicFactors;
+use crate::mult_shift::MultShift;
+use crate::{cache, cross_core, fallback, perf_event};
+use crate::{
+    Calibration, CalibrationTimeout, ClockPreference, CrossCoreSkew, FrequencySource,
+    ReferenceClock, TscOrdering, TscPolicy, TscUnavailableReason,
+};
 
 static TSC_STATE: TSCState = TSCState {
-    is_tsc_available: UnsafeCell::new(false),
-    tsc_level: UnsafeCell::new(TSCLevel::Unstable),
-    nanos_per_cycle: UnsafeCell::new(1.0),
+    is_tsc_available: AtomicBool::new(false),
+    cycles_from_anchor: AtomicU64::new(0),
+    factors: AtomicFactors::new(),
+    kernel_clock: AtomicKernelClock::new(),
+    report: Mutex::new(TSCReport {
+        calibration: None,
+        unavailable_reason: None,
+        kernel_used_tsc: false,
+    }),
 };
 
+/// Guards the calibration, which runs either from the `#[ctor]` initializer, or on
+/// first use under the `no-ctor` feature, whichever comes first. The `assume-tsc` feature
+/// keeps the initializer even with `no-ctor`, since it never checks for the calibration.
+static INIT: Once = Once::new();
+
+/// Set once TSC has been demoted at runtime. The fallback clock is then rebased
+/// so that it continues the cycle counter where TSC left it.
+static DEMOTION: OnceLock<Demotion> = OnceLock::new();
+
+/// The reference clock the calibration measures TSC against.
+static REFERENCE_CLOCK: OnceLock<ReferenceClock> = OnceLock::new();
+
+/// The amount added to the TSC of every CPU, indexed by the CPU number that Linux stores in
+/// `TSC_AUX`, so that readings on different CPUs are consistent. The CPU whose TSC is the
+/// most ahead is not corrected, so that corrected readings never run behind raw ones.
+#[cfg(feature = "per-cpu-offsets")]
+static CPU_CORRECTIONS: OnceLock<Vec<u64>> = OnceLock::new();
+
+/// The number of CPUs `TSC_AUX` can identify.
+const TSC_AUX_CPUS: usize = 1 << 12;
+
+/// The hot-path fields are atomics so that readers never observe a torn value,
+/// while writers are serialized by the `report` lock.
 struct TSCState {
-    is_tsc_available: UnsafeCell<bool>,
-    tsc_level: UnsafeCell<TSCLevel>,
-    nanos_per_cycle: UnsafeCell<f64>,
+    is_tsc_available: AtomicBool,
+    cycles_from_anchor: AtomicU64,
+    factors: AtomicFactors,
+    kernel_clock: AtomicKernelClock,
+    report: Mutex<TSCReport>,
 }
 
-unsafe impl Sync for TSCState {}
+/// The [`KernelClock`] of the current calibration, if it has a `time_zero`, guarded by a
+/// sequence lock like [`AtomicFactors`] so that perf timestamps are converted without locking.
+struct AtomicKernelClock {
+    seq: AtomicUsize,
+    /// Zero if there is no kernel clock, since the kernel never publishes a zero `mult`.
+    mult: AtomicU64,
+    shift: AtomicU32,
+    time_zero: AtomicU64,
+}
+
+impl AtomicKernelClock {
+    const fn new() -> AtomicKernelClock {
+        AtomicKernelClock {
+            seq: AtomicUsize::new(0),
+            mult: AtomicU64::new(0),
+            shift: AtomicU32::new(0),
+            time_zero: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    fn load(&self) -> Option<KernelClock> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let mult = self.mult.load(Ordering::Relaxed);
+            let shift = self.shift.load(Ordering::Relaxed);
+            let time_zero = self.time_zero.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if seq & 1 == 0 && self.seq.load(Ordering::Relaxed) == seq {
+                return (mult != 0).then_some(KernelClock {
+                    factor: MultShift { mult, shift },
+                    time_zero: Some(time_zero),
+                });
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Must be serialized with other stores.
+    fn store(&self, kernel_clock: Option<KernelClock>) {
+        let (factor, time_zero) = match kernel_clock {
+            Some(KernelClock {
+                factor,
+                time_zero: Some(time_zero),
+            }) => (factor, time_zero),
+            _ => (MultShift { mult: 0, shift: 0 }, 0),
+        };
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.mult.store(factor.mult, Ordering::Relaxed);
+        self.shift.store(factor.shift, Ordering::Relaxed);
+        self.time_zero.store(time_zero, Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
 
+struct TSCReport {
+    calibration: Option<Calibration>,
+    unavailable_reason: Option<TscUnavailableReason>,
+    /// Whether the kernel was using tsc as its current clock source when TSC was set up.
+    kernel_used_tsc: bool,
+}
+
+struct Demotion {
+    base_cycle: u64,
+    base_fallback_nanos: u64,
+    cycles_factor: MultShift,
+    /// The largest cycle handed out so far, which keeps the rebased clock monotonic
+    /// even if the fallback clock lags behind TSC at the time of the switch.
+    last_cycle: AtomicU64,
+}
+
+#[cfg(any(not(feature = "no-ctor"), feature = "assume-tsc"))]
 #[ctor::ctor]
-unsafe fn init() {
-    let tsc_level = TSCLevel::get();
-    let is_tsc_available = match &tsc_level {
-        TSCLevel::Stable { .. } => true,
-        TSCLevel::Unstable => false,
+fn init_on_startup() {
+    init();
+}
+
+/// Calibrates TSC unless it has been done already. Concurrent callers block until
+/// the calibration is done.
+pub(crate) fn init() {
+    INIT.call_once(init_state);
+}
+
+/// Returns the state, calibrating TSC first if needed.
+///
+/// This also protects `#[ctor]` initializers of other crates that run before ours.
+#[inline]
+fn state() -> &'static TSCState {
+    if !INIT.is_completed() {
+        init();
+    }
+    &TSC_STATE
+}
+
+fn init_state() {
+    #[cfg(feature = "background-calibration")]
+    if init_provisional() {
+        return;
+    }
+
+    let mut report = TSC_STATE.report.lock().unwrap();
+    match TSCLevel::get() {
+        TSCLevel::Stable {
+            calibration,
+            cycles_from_anchor,
+        } => {
+            TSC_STATE
+                .cycles_from_anchor
+                .store(cycles_from_anchor, Ordering::Relaxed);
+            publish(&calibration);
+            TSC_STATE.is_tsc_available.store(true, Ordering::Release);
+            report.calibration = Some(calibration);
+            report.kernel_used_tsc = read_clocksource("current_clocksource")
+                .map(|current| current.trim() == "tsc")
+                .unwrap_or(false);
+            cache::store(&calibration, reference_clock());
+        }
+        TSCLevel::Unstable { reason } => {
+            if cfg!(feature = "assume-tsc") {
+                abort_unusable(reason);
+            }
+            report.unavailable_reason = Some(reason);
+        }
+    }
+}
+
+/// Aborts the process, since under the `assume-tsc` feature, [`current_cycle()`] reads TSC
+/// without checking whether it is available.
+fn abort_unusable(reason: TscUnavailableReason) -> ! {
+    eprintln!("minstant: tsc is unusable, but the `assume-tsc` feature is enabled: {reason}");
+    std::process::abort()
+}
+
+/// Starts TSC with a provisional frequency, and spawns a thread calibrating it.
+///
+/// Returns `false` if TSC is unstable, if no provisional frequency could be obtained,
+/// or if the thread could not be spawned, in which case the calibration runs synchronously.
+#[cfg(feature = "background-calibration")]
+fn init_provisional() -> bool {
+    if is_tsc_usable().is_err() || known_calibration().is_some() {
+        return false;
+    }
+    let Some(calibration) = provisional_calibration() else {
+        return false;
+    };
+
+    // The thread waits for the lock, i.e. until the provisional frequency is published. It
+    // must not run inline instead: demoting TSC from within the initialization deadlocks.
+    let mut report = TSC_STATE.report.lock().unwrap();
+    let spawned = std::thread::Builder::new()
+        .name("minstant-calibration".to_owned())
+        .spawn(calibrate_in_background);
+    if spawned.is_err() {
+        return false;
+    }
+
+    TSC_STATE.cycles_from_anchor.store(tsc(), Ordering::Relaxed);
+    publish(&calibration);
+    TSC_STATE.is_tsc_available.store(true, Ordering::Release);
+    report.calibration = Some(calibration);
+    report.kernel_used_tsc = read_clocksource("current_clocksource")
+        .map(|current| current.trim() == "tsc")
+        .unwrap_or(false);
+    true
+}
+
+/// Replaces the provisional frequency with a calibrated one. Since only the conversion
+/// factors change, instants taken before and after stay comparable and monotonic.
+///
+/// If the calibration diverges, TSC is demoted to the fallback clock.
+#[cfg(feature = "background-calibration")]
+fn calibrate_in_background() {
+    let mut report = TSC_STATE.report.lock().unwrap();
+    match _cycles_per_sec_with_source() {
+        Ok((calibration, _, _)) => {
+            publish(&calibration);
+            report.calibration = Some(calibration);
+            cache::store(&calibration, reference_clock());
+            drop(report);
+            if let Err(reason) = is_tsc_synchronized(&calibration) {
+                if cfg!(feature = "assume-tsc") {
+                    abort_unusable(reason);
+                }
+                demote(reason);
+            }
+        }
+        Err(reason) => {
+            if cfg!(feature = "assume-tsc") {
+                abort_unusable(reason);
+            }
+            drop(report);
+            demote(reason);
+        }
+    }
+}
+
+/// The nominal frequency reported by CPUID, or else a single short measurement window.
+#[cfg(feature = "background-calibration")]
+fn provisional_calibration() -> Option<Calibration> {
+    let start = ReferenceTime::now();
+    let cycles_per_second = match cpuid_cycles_per_sec() {
+        Some(nominal) => nominal,
+        None => measure_window(PROVISIONAL_WINDOW_NANOS, calibration_deadline())?
+            .cycles_per_sec
+            .round() as u64,
+    };
+    Some(Calibration {
+        cycles_per_second,
+        frequency_source: FrequencySource::Provisional,
+        iterations: 0,
+        elapsed: ReferenceTime::now() - start,
+        relative_error: 0.0,
+        standard_error: f64::INFINITY,
+        converged: false,
+        kernel_clock: None,
+    })
+}
+
+/// Measures the TSC frequency again and publishes the new conversion factor.
+///
+/// `cycles_from_anchor` is deliberately left untouched: it only fixes the origin of the
+/// cycle counter, so keeping it guarantees that instants taken before and after the
+/// recalibration stay monotonic.
+///
+/// If the new calibration diverges, or times out under [`CalibrationTimeout::Fallback`],
+/// the previous factors are kept.
+///
+/// The measurement runs without holding the `report` lock, which is only taken to publish
+/// the result, so that [`clock_info()`](crate::clock_info) and the like are not blocked
+/// meanwhile.
+pub(crate) fn recalibrate() -> Option<Calibration> {
+    if !is_tsc_available() {
+        return None;
+    }
+
+    let (calibration, _, _) = _cycles_per_sec_with_source().ok()?;
+    let mut report = state().report.lock().unwrap();
+    // TSC may have been demoted during the measurement.
+    if !is_tsc_available() {
+        return None;
+    }
+    publish(&calibration);
+    report.calibration = Some(calibration);
+    drop(report);
+    cache::store(&calibration, reference_clock());
+    Some(calibration)
+}
+
+/// The fraction of the measured rate error corrected at a time, so that the noise of a
+/// single poll is damped while a persistent error still converges over a few polls.
+const RATE_CORRECTION_GAIN: f64 = 0.5;
+
+/// The largest relative change of the TSC frequency applied by a single correction.
+const MAX_RATE_CORRECTION: f64 = 0.001;
+
+/// Scales the TSC frequency towards `1 + rate_error`, where `rate_error` is the relative
+/// amount by which TSC-derived time runs ahead of the reference clock, and publishes
+/// the new conversion factor.
+///
+/// Only [`RATE_CORRECTION_GAIN`] of the error is corrected, by at most
+/// [`MAX_RATE_CORRECTION`], so that a single outlying poll cannot skew the clock.
+pub(crate) fn correct_rate(rate_error: f64) -> Option<Calibration> {
+    if !is_tsc_available() || !rate_error.is_finite() {
+        return None;
+    }
+
+    let mut report = state().report.lock().unwrap();
+    let mut calibration = report.calibration?;
+    calibration.cycles_per_second = corrected_frequency(calibration.cycles_per_second, rate_error);
+    calibration.frequency_source = FrequencySource::Corrected;
+    calibration.kernel_clock = None;
+    publish(&calibration);
+    report.calibration = Some(calibration);
+    Some(calibration)
+}
+
+fn corrected_frequency(cycles_per_second: u64, rate_error: f64) -> u64 {
+    let correction =
+        (rate_error * RATE_CORRECTION_GAIN).clamp(-MAX_RATE_CORRECTION, MAX_RATE_CORRECTION);
+    (cycles_per_second as f64 * (1.0 + correction)).round() as u64
+}
+
+/// Checks whether the kernel still trusts tsc, and switches to the fallback clock
+/// if it does not. Returns whether TSC is still available.
+///
+/// The kernel is considered to have demoted tsc if it no longer lists it as an
+/// available clock source, or if it has switched its current clock source away
+/// from tsc.
+pub(crate) fn check_clocksource() -> bool {
+    if !is_tsc_available() {
+        return false;
+    }
+    if config().clock == ClockPreference::Tsc {
+        return true;
+    }
+
+    let kernel_used_tsc = state().report.lock().unwrap().kernel_used_tsc;
+    let demoted = match read_clocksource("available_clocksource") {
+        Ok(available) if !available.split_whitespace().any(|source| source == "tsc") => {
+            Some(TscUnavailableReason::NotListedByKernel)
+        }
+        Ok(_) if kernel_used_tsc => match read_clocksource("current_clocksource") {
+            Ok(current) if current.trim() != "tsc" => {
+                Some(TscUnavailableReason::NotCurrentClocksource)
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+
+    if let Some(reason) = demoted {
+        demote(reason);
+    }
+
+    is_tsc_available()
+}
+
+/// Measures the skew of TSC across CPUs, and switches to the fallback clock if it exceeds
+/// the configured threshold.
+pub(crate) fn verify_cross_core() -> io::Result<CrossCoreSkew> {
+    let Some(calibration) = calibration() else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tsc has not been calibrated",
+        ));
+    };
+    let skew = cross_core::measure(calibration.nanos_factor())?;
+    if exceeds_max_cross_core_skew(&skew) && config().clock != ClockPreference::Tsc {
+        demote(TscUnavailableReason::CrossCoreSkew);
+    }
+    Ok(skew)
+}
+
+fn exceeds_max_cross_core_skew(skew: &CrossCoreSkew) -> bool {
+    matches!(config().max_cross_core_skew, Some(max) if skew.max_skew > max)
+        && !is_cross_core_corrected()
+}
+
+/// Whether readings of TSC are corrected by the offset of the CPU they are read on.
+fn is_cross_core_corrected() -> bool {
+    #[cfg(feature = "per-cpu-offsets")]
+    {
+        CPU_CORRECTIONS.get().is_some()
+    }
+    #[cfg(not(feature = "per-cpu-offsets"))]
+    {
+        false
+    }
+}
+
+/// Measures the offset of TSC on every CPU, and starts correcting readings by them. Returns
+/// `false` if the CPU does not support `rdtscp`, or if the offsets could not be measured.
+///
+/// CPUs outside the affinity mask of the calling thread are corrected like the first one.
+#[cfg(feature = "per-cpu-offsets")]
+fn correct_cpu_offsets(calibration: &Calibration) -> bool {
+    if !cpuid_rdtscp() {
+        return false;
+    }
+    let Ok(skew) = cross_core::measure(calibration.nanos_factor()) else {
+        return false;
     };
-    if is_tsc_available {
-        *TSC_STATE.nanos_per_cycle.get() = 1_000_000_000.0 / tsc_level.cycles_per_second() as f64;
+    let ahead = skew
+        .offsets
+        .iter()
+        .map(|offset| offset.offset_cycles)
+        .max()
+        .unwrap_or(0);
+
+    let mut corrections = vec![ahead.unsigned_abs(); TSC_AUX_CPUS];
+    for offset in skew
+        .offsets
+        .iter()
+        .filter(|offset| offset.cpu < TSC_AUX_CPUS)
+    {
+        corrections[offset.cpu] = ahead.abs_diff(offset.offset_cycles);
+    }
+    CPU_CORRECTIONS.set(corrections).is_ok()
+}
+
+/// Switches from TSC to the fallback clock. The fallback clock is rebased onto
+/// the current cycle so that instants stay monotonic across the switch.
+///
+/// Under the `assume-tsc` feature, TSC is never demoted, since [`current_cycle()`]
+/// would not notice.
+pub(crate) fn demote(reason: TscUnavailableReason) {
+    if cfg!(feature = "assume-tsc") {
+        return;
+    }
+
+    let mut report = state().report.lock().unwrap();
+    if !is_tsc_available() {
+        return;
+    }
+
+    // Reading the fallback clock first puts the rebased clock ahead of TSC, so that it is not
+    // overtaken by a reader that still reads TSC while the switch is published. A coarse clock
+    // may lag behind by up to its resolution, which is added as a margin.
+    let base_fallback_nanos = crate::current_cycle_fallback();
+    let margin = cycles_factor().apply(fallback::resolution());
+    let base_cycle = current_cycle().saturating_add(margin.min(u64::MAX as u128) as u64);
+    let demotion = Demotion {
+        base_cycle,
+        base_fallback_nanos,
+        cycles_factor: cycles_factor(),
+        last_cycle: AtomicU64::new(base_cycle),
+    };
+    if DEMOTION.set(demotion).is_ok() {
+        state().is_tsc_available.store(false, Ordering::Release);
+        if let Some(demotion) = DEMOTION.get() {
+            demotion
+                .last_cycle
+                .fetch_max(current_cycle(), Ordering::Relaxed);
+        }
+        report.unavailable_reason = Some(reason);
     }
-    *TSC_STATE.is_tsc_available.get() = is_tsc_available;
-    *TSC_STATE.tsc_level.get() = tsc_level;
-    std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
 }
 
 #[inline]
 pub(crate) fn is_tsc_available() -> bool {
-    unsafe { *TSC_STATE.is_tsc_available.get() }
+    state().is_tsc_available.load(Ordering::Acquire)
+}
+
+/// Publishes the conversion factors and the kernel clock of `calibration`. Must be called
+/// with the `report` lock held.
+fn publish(calibration: &Calibration) {
+    TSC_STATE
+        .factors
+        .store(calibration.nanos_factor(), calibration.cycles_factor());
+    TSC_STATE.kernel_clock.store(calibration.kernel_clock);
 }
 
+/// The factor converting cycles into nanoseconds.
 #[inline]
-pub(crate) fn nanos_per_cycle() -> f64 {
-    unsafe { *TSC_STATE.nanos_per_cycle.get() }
+pub(crate) fn nanos_factor() -> MultShift {
+    state().factors.load().0
 }
 
+/// The factor converting nanoseconds into cycles.
+#[inline]
+pub(crate) fn cycles_factor() -> MultShift {
+    state().factors.load().1
+}
+
+/// Converts a cycle into a perf timestamp, if TSC was calibrated from perf events.
+pub(crate) fn perf_nanos(cycle: u64) -> Option<u64> {
+    if !is_tsc_available() {
+        return None;
+    }
+    let kernel_clock = TSC_STATE.kernel_clock.load()?;
+    let tsc = cycle.wrapping_add(TSC_STATE.cycles_from_anchor.load(Ordering::Relaxed));
+    kernel_clock.perf_nanos(tsc)
+}
+
+pub(crate) fn calibration() -> Option<Calibration> {
+    state().report.lock().unwrap().calibration
+}
+
+pub(crate) fn tsc_unavailable_reason() -> Option<TscUnavailableReason> {
+    state().report.lock().unwrap().unavailable_reason
+}
+
+/// Must only be called once [`is_tsc_available()`] returned `true`.
 #[inline]
 pub(crate) fn current_cycle() -> u64 {
-    match unsafe { &*TSC_STATE.tsc_level.get() } {
-        TSCLevel::Stable {
-            cycles_from_anchor, ..
-        } => tsc().wrapping_sub(*cycles_from_anchor),
-        TSCLevel::Unstable => panic!("tsc is unstable"),
+    corrected_tsc().wrapping_sub(TSC_STATE.cycles_from_anchor.load(Ordering::Relaxed))
+}
+
+/// Reads the fallback clock, rebased onto the cycle counter if TSC has been
+/// demoted at runtime.
+// The fallback clock is never read under the `assume-tsc` feature.
+#[cfg_attr(feature = "assume-tsc", allow(dead_code))]
+#[inline]
+pub(crate) fn current_cycle_fallback() -> u64 {
+    match DEMOTION.get() {
+        Some(demotion) => {
+            let elapsed_nanos =
+                crate::current_cycle_fallback().saturating_sub(demotion.base_fallback_nanos);
+            let elapsed_cycles = demotion.cycles_factor.apply(elapsed_nanos);
+            let cycle = demotion
+                .base_cycle
+                .saturating_add(elapsed_cycles.min(u64::MAX as u128) as u64);
+            let last_cycle = demotion.last_cycle.fetch_max(cycle, Ordering::Relaxed);
+            cycle.max(last_cycle)
+        }
+        None => crate::current_cycle_fallback(),
     }
 }
 
 enum TSCLevel {
     Stable {
-        cycles_per_second: u64,
+        calibration: Calibration,
         cycles_from_anchor: u64,
     },
-    Unstable,
+    Unstable {
+        reason: TscUnavailableReason,
+    },
 }
 
 impl TSCLevel {
     fn get() -> TSCLevel {
-        if !is_tsc_stable() {
-            return TSCLevel::Unstable;
+        if let Err(reason) = is_tsc_usable() {
+            return TSCLevel::Unstable { reason };
         }
 
-        let anchor = Instant::now();
-        let (cps, cfa) = cycles_per_sec(anchor);
-        TSCLevel::Stable {
-            cycles_per_second: cps,
-            cycles_from_anchor: cfa,
-        }
+        let calibrated = match known_calibration() {
+            Some(calibration) => Ok((calibration, tsc())),
+            None => cycles_per_sec(ReferenceTime::now()),
+        };
+        let stable = calibrated.and_then(|(calibration, cycles_from_anchor)| {
+            is_tsc_synchronized(&calibration)?;
+            Ok(TSCLevel::Stable {
+                calibration,
+                cycles_from_anchor,
+            })
+        });
+        stable.unwrap_or_else(|reason| TSCLevel::Unstable { reason })
     }
+}
 
-    #[inline]
-    fn cycles_per_second(&self) -> u64 {
-        match self {
-            TSCLevel::Stable {
-                cycles_per_second, ..
-            } => *cycles_per_second,
-            TSCLevel::Unstable => panic!("tsc is unstable"),
-        }
+/// Measures the skew of TSC across CPUs if `MINSTANT_MAX_CROSS_CORE_SKEW_NS` is configured,
+/// and refuses TSC if the skew exceeds it. A failed measurement, e.g. because the affinity
+/// of threads cannot be changed, does not refuse TSC.
+///
+/// Under the `per-cpu-offsets` feature, the offsets are measured regardless, and corrected
+/// rather than checked against the maximum skew.
+fn is_tsc_synchronized(calibration: &Calibration) -> Result<(), TscUnavailableReason> {
+    #[cfg(feature = "per-cpu-offsets")]
+    correct_cpu_offsets(calibration);
+
+    if config().max_cross_core_skew.is_none()
+        || config().clock == ClockPreference::Tsc
+        || is_cross_core_corrected()
+    {
+        return Ok(());
+    }
+    match cross_core::measure(calibration.nanos_factor()) {
+        Ok(skew) if exceeds_max_cross_core_skew(&skew) => Err(TscUnavailableReason::CrossCoreSkew),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the calibration configured by `MINSTANT_TSC_HZ`, or else the one loaded from
+/// the calibration cache, so that the frequency does not have to be measured.
+fn known_calibration() -> Option<Calibration> {
+    match config().tsc_hz {
+        Some(cycles_per_second) => Some(Calibration {
+            cycles_per_second,
+            frequency_source: FrequencySource::Configured,
+            iterations: 0,
+            elapsed: Duration::ZERO,
+            relative_error: 0.0,
+            standard_error: 0.0,
+            converged: true,
+            kernel_clock: None,
+        }),
+        None => cache::load(reference_clock()),
+    }
+}
+
+/// Checks whether TSC may be used according to the configured [`ClockPreference`].
+fn is_tsc_usable() -> Result<(), TscUnavailableReason> {
+    match config().clock {
+        ClockPreference::Auto => is_tsc_stable(config().tsc_policy),
+        ClockPreference::Tsc => Ok(()),
+        ClockPreference::Fallback => Err(TscUnavailableReason::DisabledByConfig),
     }
 }
 
 /// If linux kernel detected TSCs are sync between CPUs, we can
 /// rely on the result to say tsc is stable so that no need to
 /// sync TSCs by ourselves.
-fn is_tsc_stable() -> bool {
-    let clock_source =
-        read_to_string("/sys/devices/system/clocksource/clocksource0/available_clocksource");
+///
+/// Under the strict policy, the kernel must also be currently using tsc,
+/// the CPU must advertise an invariant tsc, and the kernel command line
+/// must not disable tsc.
+fn is_tsc_stable(policy: TscPolicy) -> Result<(), TscUnavailableReason> {
+    let available = read_clocksource("available_clocksource")?;
+    if !available.split_whitespace().any(|source| source == "tsc") {
+        return Err(TscUnavailableReason::NotListedByKernel);
+    }
+
+    if policy == TscPolicy::Strict {
+        if read_clocksource("current_clocksource")?.trim() != "tsc" {
+            return Err(TscUnavailableReason::NotCurrentClocksource);
+        }
+        if !is_tsc_invariant() {
+            return Err(TscUnavailableReason::NotInvariant);
+        }
+        if let Ok(cmdline) = read_to_string("/proc/cmdline") {
+            if cmdline_disables_tsc(&cmdline) {
+                return Err(TscUnavailableReason::DisabledByKernelCmdline);
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    clock_source.map(|s| s.contains("tsc")).unwrap_or(false)
+fn read_clocksource(file: &str) -> Result<String, TscUnavailableReason> {
+    read_to_string(format!(
+        "/sys/devices/system/clocksource/clocksource0/{file}"
+    ))
+    .map_err(|err| TscUnavailableReason::SysfsUnreadable(err.kind()))
 }
 
-/// Returns (1) cycles per second and (2) cycles from anchor.
-/// The result of subtracting `cycles_from_anchor` from newly fetched TSC
-/// can be used to
-///   1. readjust TSC to begin from zero
-///   2. sync TSCs between all CPUs
-fn cycles_per_sec(anchor: Instant) -> (u64, u64) {
-    let (cps, last_monotonic, last_tsc) = _cycles_per_sec();
+/// An invariant tsc runs at a constant rate in all ACPI P-, C- and T-states.
+/// Either the kernel reports it through the cpu flags, or CPUID does.
+fn is_tsc_invariant() -> bool {
+    if let Ok(cpuinfo) = read_to_string("/proc/cpuinfo") {
+        if let Some(flags) = cpuinfo.lines().find(|line| line.starts_with("flags")) {
+            let has_flag = |name| flags.split_whitespace().any(|flag| flag == name);
+            if (has_flag("constant_tsc") && has_flag("nonstop_tsc")) || has_flag("tsc_reliable") {
+                return true;
+            }
+        }
+    }
+
+    cpuid_invariant_tsc()
+}
+
+/// Returns `true` if the kernel command line marks tsc as unstable or
+/// selects another clock source.
+fn cmdline_disables_tsc(cmdline: &str) -> bool {
+    cmdline.split_whitespace().any(|param| {
+        if param == "notsc" {
+            return true;
+        }
+        match param.split_once('=') {
+            Some(("tsc", value)) => value.split(',').any(|option| option == "unstable"),
+            Some(("clocksource", value)) => value != "tsc",
+            _ => false,
+        }
+    })
+}
+
+/// Returns (1) the calibration and (2) cycles from anchor, or `None` if the
+/// calibration diverged. The result of subtracting `cycles_from_anchor` from newly fetched TSC
+/// is used to readjust TSC to begin from zero.
+///
+/// This assumes that TSCs are synchronized between all CPUs, which is only measured
+/// by [`cross_core::measure()`].
+fn cycles_per_sec(anchor: ReferenceTime) -> Result<(Calibration, u64), TscUnavailableReason> {
+    let (calibration, last_monotonic, last_tsc) = _cycles_per_sec_with_source()?;
+    let cps = calibration.cycles_per_second();
     let nanos_from_anchor = (last_monotonic - anchor).as_nanos();
     let cycles_flied = cps as f64 * nanos_from_anchor as f64 / 1_000_000_000.0;
-    let cycles_from_anchor = last_tsc - cycles_flied.ceil() as u64;
+    let cycles_from_anchor = last_tsc
+        .checked_sub(cycles_flied.ceil() as u64)
+        .ok_or(TscUnavailableReason::CalibrationDiverged)?;
+
+    Ok((calibration, cycles_from_anchor))
+}
+
+/// The relative difference between two consecutive measurement windows below which
+/// the calibration has converged.
+const CONVERGENCE_THRESHOLD: f64 = 0.00001;
+
+/// Returns (1) the calibration, (2) last monotonic time and (3) associated tsc, or
+/// the reason why the calibration failed.
+///
+/// If enabled, the conversion the kernel publishes to perf events is preferred, since it is exactly
+/// the one of the kernel, and makes instants comparable with perf timestamps. Next, the
+/// nominal frequency reported by CPUID is preferred since it is not biased by a noisy
+/// calibration window, but it is only accepted if a quick measurement agrees with it within three
+/// standard errors, or within [`CONVERGENCE_THRESHOLD`] if that is larger. The base frequency of
+/// leaf 0x16 is rounded to MHz and rarely passes. Otherwise, the frequency is measured until it
+/// converges.
+fn _cycles_per_sec_with_source() -> Result<(Calibration, ReferenceTime, u64), TscUnavailableReason>
+{
+    let start = ReferenceTime::now();
+    let deadline = calibration_deadline();
+
+    let kernel_clock = if config().perf_event {
+        perf_event::kernel_clock()
+    } else {
+        None
+    };
+    if let Some(kernel_clock) = kernel_clock {
+        let (last_monotonic, last_tsc, _) = bracketed_sample();
+        let calibration = Calibration {
+            cycles_per_second: kernel_clock.cycles_per_second(),
+            frequency_source: FrequencySource::PerfEvent,
+            iterations: 0,
+            elapsed: last_monotonic - start,
+            relative_error: 0.0,
+            standard_error: 0.0,
+            converged: true,
+            kernel_clock: Some(kernel_clock),
+        };
+        return Ok((calibration, last_monotonic, last_tsc));
+    }
+
+    if let Some(nominal) = cpuid_cycles_per_sec() {
+        let window = measure_window(CALIBRATION_WINDOW_NANOS, deadline)
+            .ok_or(TscUnavailableReason::CalibrationDiverged)?;
+        let measured = window.cycles_per_sec;
+        let relative_error = f64::abs(nominal as f64 - measured) / measured;
+        let tolerance = f64::max(3.0 * window.standard_error, CONVERGENCE_THRESHOLD);
+        if relative_error <= tolerance {
+            let calibration = Calibration {
+                cycles_per_second: nominal,
+                frequency_source: FrequencySource::Cpuid,
+                iterations: 1,
+                elapsed: window.last_monotonic - start,
+                relative_error,
+                standard_error: window.standard_error,
+                converged: true,
+                kernel_clock: None,
+            };
+            return Ok((calibration, window.last_monotonic, window.last_tsc));
+        }
+    }
+
+    let measurement =
+        _cycles_per_sec(start, deadline).ok_or(TscUnavailableReason::CalibrationDiverged)?;
+    if !measurement.converged && config().on_calibration_timeout == CalibrationTimeout::Fallback {
+        return Err(TscUnavailableReason::CalibrationTimedOut);
+    }
+    let window = measurement.window;
+    let calibration = Calibration {
+        cycles_per_second: window.cycles_per_sec.round() as u64,
+        frequency_source: FrequencySource::Measured,
+        iterations: measurement.iterations,
+        elapsed: window.last_monotonic - start,
+        relative_error: measurement.relative_error,
+        standard_error: window.standard_error,
+        converged: measurement.converged,
+        kernel_clock: None,
+    };
+    Ok((calibration, window.last_monotonic, window.last_tsc))
+}
 
-    (cps, cycles_from_anchor)
+/// The outcome of [`_cycles_per_sec()`].
+struct Measurement {
+    window: Window,
+    iterations: u32,
+    /// The relative difference with the previous measurement window.
+    relative_error: f64,
+    converged: bool,
 }
 
-/// Returns (1) cycles per second, (2) last monotonic time and (3) associated tsc.
-fn _cycles_per_sec() -> (u64, Instant, u64) {
-    let mut cycles_per_sec;
-    let mut last_monotonic;
-    let mut last_tsc;
+/// Measures the frequency until two consecutive windows agree, or until the time or
+/// iteration limit is reached, in which case the window agreeing best with its
+/// predecessor is returned. Returns `None` if the calibration diverged.
+fn _cycles_per_sec(start: ReferenceTime, deadline: std::time::Instant) -> Option<Measurement> {
+    let config = config();
+    let mut best: Option<Measurement> = None;
     let mut old_cycles = 0.0;
+    let mut iterations = 0;
 
     loop {
-        let (t1, tsc1) = monotonic_with_tsc();
-        loop {
-            let (t2, tsc2) = monotonic_with_tsc();
-            last_monotonic = t2;
-            last_tsc = tsc2;
-            let elapsed_nanos = (t2 - t1).as_nanos();
-            if elapsed_nanos > 10_000_000 {
-                cycles_per_sec = (tsc2 - tsc1) as f64 * 1_000_000_000.0 / elapsed_nanos as f64;
-                break;
-            }
+        let window = measure_window(CALIBRATION_WINDOW_NANOS, deadline)?;
+        let (cycles_per_sec, last_monotonic) = (window.cycles_per_sec, window.last_monotonic);
+        iterations += 1;
+        let relative_error = f64::abs(cycles_per_sec - old_cycles) / cycles_per_sec;
+        let converged = relative_error < CONVERGENCE_THRESHOLD;
+        if !matches!(&best, Some(best) if best.relative_error <= relative_error) {
+            best = Some(Measurement {
+                window,
+                iterations,
+                relative_error,
+                converged,
+            });
         }
-        let delta = f64::abs(cycles_per_sec - old_cycles);
-        if delta / cycles_per_sec < 0.00001 {
+        if converged
+            || iterations >= config.max_calibration_iterations
+            || last_monotonic - start >= config.calibration_timeout
+            || std::time::Instant::now() >= deadline
+        {
             break;
         }
         old_cycles = cycles_per_sec;
     }
 
-    (cycles_per_sec.round() as u64, last_monotonic, last_tsc)
+    best.map(|best| Measurement { iterations, ..best })
 }
 
-/// Try to get tsc and monotonic time at the same time. Due to
-/// get interrupted in half way may happen, they aren't guaranteed
-/// to represent the same instant.
-fn monotonic_with_tsc() -> (Instant, u64) {
-    (Instant::now(), tsc())
+/// The length of a calibration window.
+const CALIBRATION_WINDOW_NANOS: u128 = 10_000_000;
+
+/// The least time the calibration is given before its deadline, so that a window fits
+/// even if the configured calibration time is shorter.
+const MIN_CALIBRATION_TIME: Duration = Duration::from_millis(100);
+
+/// The time by which the calibration stops measuring, as read by the monotonic clock of
+/// `std` rather than the reference clock, in case the latter does not advance.
+fn calibration_deadline() -> std::time::Instant {
+    std::time::Instant::now() + config().calibration_timeout.max(MIN_CALIBRATION_TIME)
+}
+
+/// The length of the window estimating the provisional frequency.
+#[cfg(feature = "background-calibration")]
+const PROVISIONAL_WINDOW_NANOS: u128 = 1_000_000;
+
+/// The outcome of [`measure_window()`].
+struct Window {
+    cycles_per_sec: f64,
+    /// The relative standard error of `cycles_per_sec`.
+    standard_error: f64,
+    last_monotonic: ReferenceTime,
+    /// The tsc fitted to `last_monotonic`.
+    last_tsc: u64,
+}
+
+/// The number of samples taken before any is fitted, to learn how wide the bracket
+/// of an undisturbed sample is.
+const WARMUP_SAMPLES: usize = 16;
+
+/// Measures cycles per second over a window of at least `window_nanos`, by fitting
+/// a line through bracketed samples with least squares. Samples whose bracket is more
+/// than twice as wide as the narrowest one were likely disturbed, e.g. preempted, and
+/// are discarded.
+///
+/// Returns `None` if TSC went backwards during the window, which happens if the thread
+/// migrates between CPUs whose TSCs are not synchronized. If `deadline` passes before the
+/// window is complete, the samples taken so far are fitted, or `None` is returned if there
+/// are too few of them.
+fn measure_window(window_nanos: u128, deadline: std::time::Instant) -> Option<Window> {
+    let mut min_width = u64::MAX;
+    for _ in 0..WARMUP_SAMPLES {
+        min_width = min_width.min(bracketed_sample().2);
+    }
+
+    let (t1, tsc1, _) = bracketed_sample();
+    let mut fit = LeastSquares::default();
+    let mut last = (t1, tsc1);
+    loop {
+        let (t2, tsc2, width) = bracketed_sample();
+        min_width = min_width.min(width);
+        if width <= min_width.saturating_mul(2) {
+            if tsc2 < last.1 {
+                return None;
+            }
+            let nanos = (t2 - t1).as_nanos() as f64;
+            fit.add(nanos, (tsc2 - tsc1) as f64);
+            last = (t2, tsc2);
+        }
+        if (t2 - t1).as_nanos() > window_nanos && fit.len() >= 3 {
+            break;
+        }
+        if std::time::Instant::now() >= deadline {
+            if fit.len() >= 3 {
+                break;
+            }
+            return None;
+        }
+    }
+
+    let (slope, standard_error) = fit.slope()?;
+    let cycles_per_sec = slope * 1_000_000_000.0;
+    if cycles_per_sec < 1.0 {
+        return None;
+    }
+    let last_nanos = (last.0 - t1).as_nanos() as f64;
+    let last_tsc = tsc1 + fit.at(last_nanos).max(0.0).round() as u64;
+    Some(Window {
+        cycles_per_sec,
+        standard_error: standard_error / slope,
+        last_monotonic: last.0,
+        last_tsc,
+    })
+}
+
+/// A streaming least-squares fit of `y = intercept + slope * x`, with the moments
+/// updated in the numerically stable way of Welford.
+#[derive(Debug, Default)]
+struct LeastSquares {
+    len: usize,
+    mean_x: f64,
+    mean_y: f64,
+    /// The sum of squared deviations of `x`.
+    sxx: f64,
+    /// The sum of squared deviations of `y`.
+    syy: f64,
+    /// The sum of products of the deviations of `x` and `y`.
+    sxy: f64,
+}
+
+impl LeastSquares {
+    fn add(&mut self, x: f64, y: f64) {
+        self.len += 1;
+        let n = self.len as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / n;
+        self.mean_y += dy / n;
+        self.sxx += dx * (x - self.mean_x);
+        self.syy += dy * (y - self.mean_y);
+        self.sxy += dx * (y - self.mean_y);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns (1) the slope and (2) its standard error, or `None` if there are fewer
+    /// than three points or all of them share the same `x`.
+    fn slope(&self) -> Option<(f64, f64)> {
+        if self.len < 3 || self.sxx <= 0.0 {
+            return None;
+        }
+        let slope = self.sxy / self.sxx;
+        let residuals = (self.syy - slope * self.sxy).max(0.0);
+        let variance = residuals / (self.len - 2) as f64;
+        Some((slope, (variance / self.sxx).sqrt()))
+    }
+
+    /// Returns the fitted `y` at `x`.
+    fn at(&self, x: f64) -> f64 {
+        self.mean_y + self.sxy / self.sxx * (x - self.mean_x)
+    }
+}
+
+/// Executes `cpuid` for `leaf`.
+// `__cpuid` is only `unsafe` on older toolchains.
+#[allow(unused_unsafe)]
+#[inline]
+fn cpuid(leaf: u32) -> CpuidResult {
+    unsafe { __cpuid(leaf) }
+}
+
+/// Reads the nominal TSC frequency from CPUID.
+///
+/// Leaf 0x15 reports the TSC/crystal ratio and, on most recent CPUs, the crystal
+/// frequency. When the crystal frequency is not enumerated, the processor base
+/// frequency from leaf 0x16 is used instead, which matches the TSC frequency on
+/// the CPUs that report it.
+fn cpuid_cycles_per_sec() -> Option<u64> {
+    let max_leaf = cpuid(0).eax;
+
+    if max_leaf >= 0x15 {
+        let leaf = cpuid(0x15);
+        let (denominator, numerator, crystal_hz) = (leaf.eax, leaf.ebx, leaf.ecx);
+        if denominator != 0 && numerator != 0 && crystal_hz != 0 {
+            return Some(crystal_hz as u64 * numerator as u64 / denominator as u64);
+        }
+    }
+
+    if max_leaf >= 0x16 {
+        let base_mhz = cpuid(0x16).eax & 0xffff;
+        if base_mhz != 0 {
+            return Some(base_mhz as u64 * 1_000_000);
+        }
+    }
+
+    None
+}
+
+/// Reads the invariant tsc bit from CPUID leaf 0x80000007.
+fn cpuid_invariant_tsc() -> bool {
+    let max_extended_leaf = cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Reads the rdtscp bit from CPUID leaf 0x80000001.
+fn cpuid_rdtscp() -> bool {
+    let max_extended_leaf = cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0001 && cpuid(0x8000_0001).edx & (1 << 27) != 0
+}
+
+/// Returns the reference clock the calibration measures TSC against: the configured one,
+/// or `CLOCK_MONOTONIC` if the kernel does not support it.
+pub(crate) fn reference_clock() -> ReferenceClock {
+    *REFERENCE_CLOCK.get_or_init(|| {
+        let configured = config().reference_clock;
+        if clock_gettime(clockid(configured)).is_some() {
+            configured
+        } else {
+            ReferenceClock::Monotonic
+        }
+    })
+}
+
+fn clockid(clock: ReferenceClock) -> libc::clockid_t {
+    match clock {
+        ReferenceClock::MonotonicRaw => libc::CLOCK_MONOTONIC_RAW,
+        ReferenceClock::Monotonic => libc::CLOCK_MONOTONIC,
+    }
+}
+
+fn clock_gettime(clockid: libc::clockid_t) -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clockid, &mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// A reading of the [`reference_clock()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ReferenceTime(Duration);
+
+/// Reads the reference clock, as the time elapsed since an arbitrary origin.
+pub(crate) fn reference_now() -> Duration {
+    ReferenceTime::now().0
+}
+
+impl ReferenceTime {
+    #[inline]
+    fn now() -> ReferenceTime {
+        ReferenceTime(clock_gettime(clockid(reference_clock())).unwrap_or_default())
+    }
+}
+
+impl Sub for ReferenceTime {
+    type Output = Duration;
+
+    fn sub(self, earlier: ReferenceTime) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+/// Reads the reference time between two tsc reads. Returns (1) the reference time,
+/// (2) the tsc in the middle of the bracket and (3) the width of the bracket, which
+/// bounds how far apart the two readings may be if the thread got interrupted.
+fn bracketed_sample() -> (ReferenceTime, u64, u64) {
+    let before = tsc();
+    let monotonic = ReferenceTime::now();
+    let after = tsc();
+    let width = after.saturating_sub(before);
+    (monotonic, before + width / 2, width)
+}
+
+/// Like [`current_cycle()`], but orders the read of TSC relative to the surrounding
+/// instructions. Must only be called once [`is_tsc_available()`] returned `true`.
+#[inline]
+pub(crate) fn current_cycle_ordered(ordering: TscOrdering) -> u64 {
+    let tsc = match ordering {
+        TscOrdering::Fenced => {
+            lfence();
+            corrected_tsc()
+        }
+        TscOrdering::Rdtscp => corrected_tscp(),
+        TscOrdering::Start => {
+            serialize();
+            corrected_tsc()
+        }
+        TscOrdering::Stop => {
+            let tsc = corrected_tscp();
+            serialize();
+            tsc
+        }
+    };
+    tsc.wrapping_sub(TSC_STATE.cycles_from_anchor.load(Ordering::Relaxed))
+}
+
+/// Reads TSC with `rdtscp`, or with `lfence; rdtsc` if the CPU does not support `rdtscp`.
+/// The read is corrected like in [`corrected_tsc()`].
+#[inline]
+fn corrected_tscp() -> u64 {
+    if !has_rdtscp() {
+        lfence();
+        return corrected_tsc();
+    }
+    let (tsc, _cpu) = tscp();
+    #[cfg(feature = "per-cpu-offsets")]
+    if let Some(corrections) = CPU_CORRECTIONS.get() {
+        return tsc.wrapping_add(corrections[_cpu]);
+    }
+    tsc
+}
+
+#[inline]
+fn has_rdtscp() -> bool {
+    static HAS_RDTSCP: OnceLock<bool> = OnceLock::new();
+    *HAS_RDTSCP.get_or_init(cpuid_rdtscp)
+}
+
+/// Waits until all earlier instructions completed before starting later ones.
+#[inline]
+fn lfence() {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::_mm_lfence;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::_mm_lfence;
+
+    unsafe { _mm_lfence() }
+}
+
+/// Executes `cpuid`, which completes all earlier instructions, including stores, before
+/// starting later ones.
+#[inline]
+fn serialize() {
+    cpuid(0);
+}
+
+/// Reads TSC, corrected by the offset of the CPU it is read on once the offsets are measured
+/// under the `per-cpu-offsets` feature.
+#[inline]
+fn corrected_tsc() -> u64 {
+    #[cfg(feature = "per-cpu-offsets")]
+    if let Some(corrections) = CPU_CORRECTIONS.get() {
+        let (tsc, cpu) = tscp();
+        return tsc.wrapping_add(corrections[cpu]);
+    }
+    tsc()
+}
+
+/// Reads TSC along with the number of the CPU it was read on, which Linux stores in the
+/// low 12 bits of `TSC_AUX`. `rdtscp` cannot be executed before earlier instructions
+/// complete, so it also cannot read TSC on a CPU the thread migrated away from.
+#[inline]
+fn tscp() -> (u64, usize) {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::__rdtscp;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::__rdtscp;
+
+    let mut aux = 0;
+    let tsc = unsafe { __rdtscp(&mut aux) };
+    (tsc, aux as usize % TSC_AUX_CPUS)
 }
 
 #[inline]
@@ -156,3 +1194,152 @@ fn tsc() -> u64 {
 
     unsafe { _rdtsc() }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_squares() {
+        let mut fit = LeastSquares::default();
+        for i in 0..1000 {
+            let x = 1e9 + i as f64 * 25.0;
+            let noise = [-3.0, 1.0, 2.0][i % 3];
+            fit.add(x, 2.1 * x + 1e6 + noise);
+        }
+        let (slope, standard_error) = fit.slope().unwrap();
+        assert!((slope - 2.1).abs() < 1e-6, "slope: {}", slope);
+        assert!(standard_error > 0.0 && standard_error < 1e-4);
+        assert!((fit.at(1e9 + 25_000.0) - 2.1 * (1e9 + 25_000.0) - 1e6).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_measure_window() {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        let window = measure_window(1_000_000, deadline).unwrap();
+        assert!(window.cycles_per_sec > 0.0);
+        assert!(window.standard_error < 0.01);
+
+        // A window too long to complete is cut short by the deadline.
+        let start = std::time::Instant::now();
+        let window = measure_window(u128::MAX, start + Duration::from_millis(10)).unwrap();
+        assert!(window.cycles_per_sec > 0.0);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_corrected_frequency() {
+        assert_eq!(corrected_frequency(2_000_000_000, 0.0001), 2_000_100_000);
+        assert_eq!(corrected_frequency(2_000_000_000, -0.0001), 1_999_900_000);
+        // A single correction is bounded, however large the measured error.
+        assert_eq!(corrected_frequency(2_000_000_000, 0.5), 2_002_000_000);
+        assert_eq!(corrected_frequency(2_000_000_000, -0.5), 1_998_000_000);
+    }
+
+    #[test]
+    fn test_atomic_kernel_clock() {
+        let kernel_clock = KernelClock {
+            factor: MultShift {
+                mult: 1 << 30,
+                shift: 31,
+            },
+            time_zero: Some(u64::MAX - 10),
+        };
+        let atomic = AtomicKernelClock::new();
+        assert_eq!(atomic.load(), None);
+        atomic.store(Some(kernel_clock));
+        assert_eq!(atomic.load(), Some(kernel_clock));
+        // Without a `time_zero`, perf timestamps cannot be produced.
+        atomic.store(Some(KernelClock {
+            time_zero: None,
+            ..kernel_clock
+        }));
+        assert_eq!(atomic.load(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "per-cpu-offsets")]
+    fn test_cpu_corrections() {
+        init();
+        if is_tsc_available() && cpuid_rdtscp() {
+            let corrections = CPU_CORRECTIONS.get().unwrap();
+            assert_eq!(corrections.len(), TSC_AUX_CPUS);
+            assert!(corrections.contains(&0));
+            let (_, cpu) = tscp();
+            assert!(cpu < TSC_AUX_CPUS);
+        }
+    }
+
+    /// Switching to the fallback clock is irreversible for the whole process, so the test
+    /// runs itself again in a child process to demote TSC there.
+    #[test]
+    #[cfg(not(feature = "assume-tsc"))]
+    fn test_demotion_is_monotonic() {
+        use std::sync::Arc;
+        use std::thread;
+
+        use crate::Instant;
+
+        if std::env::var_os("MINSTANT_TEST_DEMOTION").is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "tsc_now::tests::test_demotion_is_monotonic"])
+                .env("MINSTANT_TEST_DEMOTION", "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        init();
+        let was_tsc_available = is_tsc_available();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    let mut prev = Instant::now();
+                    while !stop.load(Ordering::Relaxed) {
+                        let now = Instant::now();
+                        assert!(now >= prev);
+                        prev = now;
+                    }
+                })
+            })
+            .collect();
+
+        let before = Instant::now();
+        demote(TscUnavailableReason::NotCurrentClocksource);
+        thread::sleep(Duration::from_millis(10));
+        let after = Instant::now();
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert!(!is_tsc_available());
+        if was_tsc_available {
+            assert_eq!(
+                crate::tsc_unavailable_reason(),
+                Some(TscUnavailableReason::NotCurrentClocksource)
+            );
+        }
+        assert!(after > before);
+        // The coarse fallback clock only advances every few milliseconds. Otherwise, allow for the
+        // rounding of the fallback clock through the cycles of TSC.
+        if !cfg!(feature = "fallback-coarse") {
+            assert!(after.duration_since(before) >= Duration::from_micros(9_999));
+        }
+        assert!(Instant::now() >= after);
+    }
+
+    #[test]
+    fn test_cmdline_disables_tsc() {
+        assert!(!cmdline_disables_tsc("ro quiet splash\n"));
+        assert!(!cmdline_disables_tsc("tsc=reliable clocksource=tsc"));
+        assert!(cmdline_disables_tsc("ro tsc=unstable quiet"));
+        assert!(cmdline_disables_tsc("tsc=reliable,unstable"));
+        assert!(cmdline_disables_tsc("clocksource=hpet"));
+        assert!(cmdline_disables_tsc("notsc"));
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A background thread that periodically calls [`check_clocksource()`](crate::check_clocksource),
+/// so that [`Instant::now()`](crate::Instant::now) switches to the fallback clock soon after the
+/// kernel demotes [TSC](https://en.wikipedia.org/wiki/Time_Stamp_Counter).
+///
+/// The watcher stops when it is dropped, or once TSC is no longer available.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use minstant::ClocksourceWatcher;
+///
+/// let watcher = ClocksourceWatcher::spawn(Duration::from_secs(10)).unwrap();
+/// // ...
+/// drop(watcher);
+/// ```
+#[derive(Debug)]
+pub struct ClocksourceWatcher {
+    _worker: Worker,
+}
+
+impl ClocksourceWatcher {
+    /// Spawns a thread checking the kernel clock sources every `interval`.
+    pub fn spawn(interval: Duration) -> io::Result<ClocksourceWatcher> {
+        let worker = Worker::spawn("minstant-watcher", interval, crate::check_clocksource)?;
+        Ok(ClocksourceWatcher { _worker: worker })
+    }
+}
+
+/// A thread running a task periodically until the task returns `false` or the
+/// worker is dropped.
+#[derive(Debug)]
+pub(crate) struct Worker {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub(crate) fn spawn(
+        name: &str,
+        interval: Duration,
+        mut task: impl FnMut() -> bool + Send + 'static,
+    ) -> io::Result<Worker> {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if !task() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Worker {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up.
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}